@@ -2,7 +2,19 @@ use ethabi;
 use serde_json;
 use std::collections::HashMap;
 use std::sync::RwLock;
-use web3::types::{Address, BlockNumber, Filter, FilterBuilder, Log, H256, U64};
+use web3::types::{Address, BlockNumber, Filter, FilterBuilder, Log, H256, U256, U64};
+
+/// TokenNetwork events which only matter for channels we participate in. They are
+/// fetched by per channel filters instead of the filter of the whole token network.
+const CHANNEL_EVENTS: &[&str] = &[
+    "ChannelNewDeposit",
+    "ChannelWithdraw",
+    "NonClosingBalanceProofUpdated",
+    "ChannelUnlocked",
+];
+
+/// A filter is identified by the contract address and, for channel filters, the channel identifier.
+pub type FilterID = (Address, Option<U256>);
 
 #[derive(Clone, Debug)]
 pub struct Event {
@@ -13,10 +25,68 @@ pub struct Event {
     pub data: Vec<ethabi::Token>,
 }
 
+/// Log filter for the events of a single deployed contract. Channel filters only match
+/// logs whose first indexed topic is their channel identifier.
+#[derive(Clone, Debug)]
+pub struct ContractFilter {
+    pub contract_name: String,
+    pub address: Address,
+    pub channel_identifier: Option<U256>,
+    pub last_synced_block: U64,
+    topics: Vec<H256>,
+    channel_opened_topic: Option<H256>,
+}
+
+impl ContractFilter {
+    pub fn id(&self) -> FilterID {
+        (self.address, self.channel_identifier)
+    }
+
+    pub fn build(&self, from_block: U64, to_block: U64) -> Filter {
+        let channel_topic = self.channel_identifier.map(|channel_identifier| {
+            let mut topic = [0u8; 32];
+            channel_identifier.to_big_endian(&mut topic);
+            vec![H256::from(topic)]
+        });
+        FilterBuilder::default()
+            .address(vec![self.address])
+            .topics(Some(self.topics.clone()), channel_topic, None, None)
+            .from_block(BlockNumber::Number(from_block))
+            .to_block(BlockNumber::Number(to_block))
+            .build()
+    }
+
+    /// Filters for the `ChannelOpened` logs of channels `participant` takes part in, one for
+    /// each indexed participant topic. Only token network filters have them.
+    pub fn build_participant_filters(&self, participant: Address, from_block: U64, to_block: U64) -> Vec<Filter> {
+        let channel_opened_topic = match self.channel_opened_topic {
+            Some(channel_opened_topic) => channel_opened_topic,
+            None => return vec![],
+        };
+        let participant_topic = vec![H256::from(participant)];
+        vec![(Some(participant_topic.clone()), None), (None, Some(participant_topic))]
+            .into_iter()
+            .map(|(participant1_topic, participant2_topic)| {
+                FilterBuilder::default()
+                    .address(vec![self.address])
+                    .topics(
+                        Some(vec![channel_opened_topic]),
+                        None,
+                        participant1_topic,
+                        participant2_topic,
+                    )
+                    .from_block(BlockNumber::Number(from_block))
+                    .to_block(BlockNumber::Number(to_block))
+                    .build()
+            })
+            .collect()
+    }
+}
+
 #[derive(Default)]
 pub struct ContractRegistry {
     contracts: HashMap<String, ethabi::Contract>,
-    pub filters: RwLock<HashMap<String, HashMap<String, Filter>>>,
+    pub filters: RwLock<HashMap<FilterID, ContractFilter>>,
 }
 
 impl ContractRegistry {
@@ -39,34 +109,91 @@ impl ContractRegistry {
         registry
     }
 
-    pub fn create_contract_event_filters(
+    /// Installs a filter for the events of `contract_name` deployed at `contract_address`.
+    /// Returns the new filter, or `None` if the address is already filtered or the contract is unknown.
+    pub fn create_contract_event_filter(
         &self,
         contract_name: String,
         contract_address: Address,
-        start_block_number: BlockNumber,
-    ) {
-        let mut contracts_map = self.filters.write().unwrap();
-        if contracts_map.get(&contract_name).is_none() {
-            for (name, contract) in &self.contracts {
-                if name.as_str() != contract_name.as_str() {
-                    continue;
-                }
+        last_synced_block: U64,
+    ) -> Option<ContractFilter> {
+        self.create_filter(contract_name, contract_address, None, last_synced_block)
+    }
 
-                contracts_map.insert(contract_name.clone(), HashMap::new());
-                let filters = contracts_map.get_mut(&contract_name).unwrap();
-                let events = contract.events();
-                for event in events {
-                    let event_sig = event.signature();
-                    let filter = FilterBuilder::default()
-                        .address(vec![contract_address])
-                        .topics(Some(vec![event_sig]), None, None, None)
-                        .from_block(start_block_number)
-                        .to_block(BlockNumber::Latest)
-                        .build();
-                    filters.insert(event.name.clone(), filter);
-                }
-            }
+    pub fn create_channel_event_filter(
+        &self,
+        token_network_address: Address,
+        channel_identifier: U256,
+        last_synced_block: U64,
+    ) -> Option<ContractFilter> {
+        self.create_filter(
+            super::TOKEN_NETWORK.to_string(),
+            token_network_address,
+            Some(channel_identifier),
+            last_synced_block,
+        )
+    }
+
+    /// Installs a filter unless one with the same id exists already. Returns the new
+    /// filter, or `None` if nothing was installed.
+    pub fn create_filter(
+        &self,
+        contract_name: String,
+        contract_address: Address,
+        channel_identifier: Option<U256>,
+        last_synced_block: U64,
+    ) -> Option<ContractFilter> {
+        let mut filters = self.filters.write().unwrap();
+        if filters.contains_key(&(contract_address, channel_identifier)) {
+            return None;
         }
+
+        let contract = self.contracts.get(&contract_name)?;
+        let topics = contract
+            .events()
+            .filter(|event| CHANNEL_EVENTS.contains(&event.name.as_str()) == channel_identifier.is_some())
+            .map(|event| event.signature())
+            .collect();
+        let channel_opened_topic = if contract_name == super::TOKEN_NETWORK && channel_identifier.is_none() {
+            contract.event("ChannelOpened").ok().map(|event| event.signature())
+        } else {
+            None
+        };
+        let filter = ContractFilter {
+            contract_name,
+            address: contract_address,
+            channel_identifier,
+            last_synced_block,
+            topics,
+            channel_opened_topic,
+        };
+        filters.insert(filter.id(), filter.clone());
+        Some(filter)
+    }
+
+    pub fn remove_filter(&self, filter_id: &FilterID) -> Option<ContractFilter> {
+        self.filters.write().unwrap().remove(filter_id)
+    }
+
+    pub fn set_last_synced_block(&self, filter_id: &FilterID, last_synced_block: U64) -> Option<ContractFilter> {
+        let mut filters = self.filters.write().unwrap();
+        let filter = filters.get_mut(filter_id)?;
+        filter.last_synced_block = last_synced_block;
+        Some(filter.clone())
+    }
+
+    /// Moves every filter which is synced past `block_number` back to it.
+    /// Returns the filters which were changed.
+    pub fn rewind_filters(&self, block_number: U64) -> Vec<ContractFilter> {
+        let mut filters = self.filters.write().unwrap();
+        filters
+            .values_mut()
+            .filter(|filter| filter.last_synced_block > block_number)
+            .map(|filter| {
+                filter.last_synced_block = block_number;
+                filter.clone()
+            })
+            .collect()
     }
 
     pub fn log_to_event(&self, log: &Log) -> Option<Event> {
@@ -85,8 +212,8 @@ impl ContractRegistry {
                     if log.topics.len() >= 2 {
                         let indexed_inputs: Vec<&ethabi::EventParam> =
                             event.inputs.iter().filter(|input| input.indexed).collect();
-                        for topic in &log.topics[1..] {
-                            if let Ok(decoded_value) = ethabi::decode(&[indexed_inputs[0].kind.clone()], &topic.0) {
+                        for (topic, input) in log.topics[1..].iter().zip(indexed_inputs.iter()) {
+                            if let Ok(decoded_value) = ethabi::decode(std::slice::from_ref(&input.kind), &topic.0) {
                                 data.push(decoded_value[0].clone());
                             }
                         }
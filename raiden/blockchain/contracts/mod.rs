@@ -1,24 +1,168 @@
 use super::helpers::parse_address;
-use web3::types::Address;
+use crate::enums::ChainID;
+use crate::errors::RaidenError;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use web3::types::{Address, U64};
 
 pub mod abi;
 
 pub const CONTRACTS: &str = include_str!("data/contracts.json");
+const DEVELOPMENT_CONTRACTS_VERSION: &str = "development";
+
+const DEPLOYMENT_ROPSTEN: &str = include_str!("data/deployment_ropsten.json");
+const DEPLOYMENT_RINKEBY: &str = include_str!("data/deployment_rinkeby.json");
+const DEPLOYMENT_GOERLI: &str = include_str!("data/deployment_goerli.json");
 const DEPLOYMENT_KOVAN: &str = include_str!("data/deployment_kovan.json");
+const DEPLOYMENT_PRIVATE_NET: &str = include_str!("data/deployment_private_net.json");
+const DEPLOYMENT_SERVICES_ROPSTEN: &str = include_str!("data/deployment_services_ropsten.json");
+const DEPLOYMENT_SERVICES_RINKEBY: &str = include_str!("data/deployment_services_rinkeby.json");
+const DEPLOYMENT_SERVICES_GOERLI: &str = include_str!("data/deployment_services_goerli.json");
+const DEPLOYMENT_SERVICES_KOVAN: &str = include_str!("data/deployment_services_kovan.json");
+
+pub const TOKEN_NETWORK_REGISTRY: &str = "TokenNetworkRegistry";
+pub const SECRET_REGISTRY: &str = "SecretRegistry";
+pub const TOKEN_NETWORK: &str = "TokenNetwork";
+
+/// Chain id of the private network `deployment_private_net.json` was deployed on.
+const PRIVATE_NET_CHAIN_ID: u64 = 17;
+
+#[derive(Clone, Debug)]
+pub struct DeployedContract {
+    pub address: Address,
+    pub block_number: U64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ContractDeployment {
+    pub contracts: HashMap<String, DeployedContract>,
+}
+
+impl ContractDeployment {
+    /// Parses deployment files as shipped in `data/`, later files override contracts of earlier ones.
+    pub fn from_json(deployment_files: &[&str]) -> Result<ContractDeployment, RaidenError> {
+        let mut contracts = HashMap::new();
+
+        for deployment_file in deployment_files {
+            let deployment_data: serde_json::Value =
+                serde_json::from_str(deployment_file).map_err(|e| RaidenError {
+                    msg: format!("Could not parse deployment data: {}", e),
+                })?;
+            let deployed_contracts = deployment_data
+                .get("contracts")
+                .and_then(|contracts| contracts.as_object())
+                .ok_or_else(|| RaidenError {
+                    msg: String::from("Deployment data has no contracts"),
+                })?;
+
+            for (name, contract) in deployed_contracts.iter() {
+                let address = contract
+                    .get("address")
+                    .and_then(|address| address.as_str())
+                    .and_then(|address| parse_address(address.to_string()));
+                let block_number = contract.get("block_number").and_then(|block| block.as_u64());
+
+                match (address, block_number) {
+                    (Some(address), Some(block_number)) => {
+                        contracts.insert(
+                            name.clone(),
+                            DeployedContract {
+                                address,
+                                block_number: U64::from(block_number),
+                            },
+                        );
+                    }
+                    _ => {
+                        return Err(RaidenError {
+                            msg: format!("Invalid deployment data for {}", name),
+                        })
+                    }
+                }
+            }
+        }
+
+        Ok(ContractDeployment { contracts })
+    }
 
-pub fn get_token_network_registry_address() -> Address {
-    let contracts_data: serde_json::Value = serde_json::from_str(DEPLOYMENT_KOVAN).unwrap();
+    pub fn get(&self, contract_name: &str) -> Option<&DeployedContract> {
+        self.contracts.get(contract_name)
+    }
+
+    pub fn token_network_registry(&self) -> Option<&DeployedContract> {
+        self.get(TOKEN_NETWORK_REGISTRY)
+    }
+
+    pub fn secret_registry(&self) -> Option<&DeployedContract> {
+        self.get(SECRET_REGISTRY)
+    }
+}
+
+pub fn get_contract_deployment(chain_id: &ChainID) -> Result<ContractDeployment, RaidenError> {
+    let deployment_files: &[&str] = match chain_id {
+        ChainID::Ropsten => &[DEPLOYMENT_ROPSTEN, DEPLOYMENT_SERVICES_ROPSTEN],
+        ChainID::Rinkeby => &[DEPLOYMENT_RINKEBY, DEPLOYMENT_SERVICES_RINKEBY],
+        ChainID::Goerli => &[DEPLOYMENT_GOERLI, DEPLOYMENT_SERVICES_GOERLI],
+        ChainID::Kovan => &[DEPLOYMENT_KOVAN, DEPLOYMENT_SERVICES_KOVAN],
+        ChainID::Private(PRIVATE_NET_CHAIN_ID) => &[DEPLOYMENT_PRIVATE_NET],
+        ChainID::Mainnet | ChainID::Private(_) => {
+            return Err(RaidenError {
+                msg: format!("No contracts deployment available for chain {:?}", chain_id),
+            })
+        }
+    };
+    ContractDeployment::from_json(deployment_files)
+}
+
+/// Resolves the deployment to run with. A user supplied deployment file replaces the bundled
+/// data, and a user supplied registry address replaces whichever TokenNetworkRegistry was found.
+pub fn load_contract_deployment(
+    chain_id: &ChainID,
+    deployment_file: Option<&Path>,
+    registry_address: Option<Address>,
+) -> Result<ContractDeployment, RaidenError> {
+    let mut contract_deployment = match deployment_file {
+        Some(deployment_file) => {
+            let deployment_data = fs::read_to_string(deployment_file).map_err(|e| RaidenError {
+                msg: format!("Could not read {}: {}", deployment_file.display(), e),
+            })?;
+            ContractDeployment::from_json(&[&deployment_data])?
+        }
+        None => match get_contract_deployment(chain_id) {
+            Ok(contract_deployment) => contract_deployment,
+            Err(_) if registry_address.is_some() => ContractDeployment {
+                contracts: HashMap::new(),
+            },
+            Err(e) => return Err(e),
+        },
+    };
+
+    if let Some(registry_address) = registry_address {
+        // The deployment block of an overridden registry is unknown unless it is the one we already have.
+        let block_number = match contract_deployment.token_network_registry() {
+            Some(registry) if registry.address == registry_address => registry.block_number,
+            _ => U64::zero(),
+        };
+        contract_deployment.contracts.insert(
+            TOKEN_NETWORK_REGISTRY.to_string(),
+            DeployedContract {
+                address: registry_address,
+                block_number,
+            },
+        );
+    }
+
+    Ok(contract_deployment)
+}
 
-    let registry_address = contracts_data
-        .get("contracts")
-        .unwrap()
-        .get("TokenNetworkRegistry")
-        .unwrap()
-        .get("address")
-        .unwrap();
+pub fn get_contracts_version() -> String {
+    let contracts_data: serde_json::Value = serde_json::from_str(CONTRACTS).unwrap();
 
-    if let Some(parsed_address) = parse_address(registry_address.as_str().unwrap().to_string()) {
-        return parsed_address;
+    match contracts_data
+        .get("contracts_version")
+        .and_then(|version| version.as_str())
+    {
+        Some(version) => version.to_string(),
+        None => DEVELOPMENT_CONTRACTS_VERSION.to_string(),
     }
-    Address::zero()
 }
@@ -3,11 +3,17 @@ use crate::blockchain::contracts::abi::Event;
 use crate::constants;
 use crate::enums::StateChange;
 use crate::transfer::state::{
-    CanonicalIdentifier, ChainState, ChannelState, TokenNetworkState, TransactionExecutionStatus, TransactionResult,
+    CanonicalIdentifier, ChainState, ChannelState, TokenNetworkState, TransactionChannelDeposit,
+    TransactionExecutionStatus, TransactionResult,
 };
-use crate::transfer::state_change::{ContractReceiveChannelOpened, ContractReceiveTokenNetworkCreated};
+use crate::transfer::state_change::{
+    ContractReceiveChannelClosed, ContractReceiveChannelDeposit, ContractReceiveChannelOpened,
+    ContractReceiveChannelSettled, ContractReceiveRouteNew, ContractReceiveSecretReveal,
+    ContractReceiveTokenNetworkCreated, ContractReceiveUpdateTransfer,
+};
+use crate::transfer::views;
 use ethabi::Token;
-use web3::types::{Address, Log, U256, U64};
+use web3::types::{Address, Log, H256, U256, U64};
 
 fn create_token_network_created_state_change(base_event: Event, log: &Log) -> Option<StateChange> {
     let token_address = match base_event.data[0] {
@@ -31,7 +37,14 @@ fn create_token_network_created_state_change(base_event: Event, log: &Log) -> Op
     ))
 }
 
-fn create_channel_opened_state_change(chain_state: &ChainState, base_event: Event, log: &Log) -> Option<StateChange> {
+/// Every channel feeds the network graph. Only logs the RPC node matched on our address
+/// as a participant, see `FetchedLog::our_channel`, become channel state.
+fn create_channel_opened_state_change(
+    chain_state: &ChainState,
+    base_event: Event,
+    log: &Log,
+    our_channel: bool,
+) -> Option<StateChange> {
     let channel_identifier = match base_event.data[0] {
         Token::Uint(identifier) => identifier,
         _ => U256::zero(),
@@ -49,23 +62,32 @@ fn create_channel_opened_state_change(chain_state: &ChainState, base_event: Even
         _ => U256::zero(),
     };
 
-    let partner_address: Address;
     let our_address = chain_state.our_address;
-    if participant1 == our_address {
-        partner_address = participant2;
+    let partner_address = if !our_channel {
+        let canonical_identifier = CanonicalIdentifier {
+            chain_identifier: u64::from(chain_state.chain_id.clone()),
+            token_network_address: log.address,
+            channel_identifier,
+        };
+        return Some(StateChange::ContractReceiveRouteNew(ContractReceiveRouteNew::new(
+            base_event.transaction_hash,
+            canonical_identifier,
+            participant1,
+            participant2,
+            base_event.block_number,
+            base_event.block_hash,
+        )));
+    } else if participant1 == our_address {
+        participant2
     } else {
-        partner_address = participant1;
-    }
-    // } else if participant2 == our_address {
-    //     partner_address = participant1;
-    // } else {
-    //     return None;
-    // }
+        participant1
+    };
 
-    let chain_identifier = 1;
+    let chain_identifier = u64::from(chain_state.chain_id.clone());
     let token_network_address = log.address;
-    let token_address = Address::zero();
-    let token_network_registry_address = Address::zero();
+    let token_address = views::get_token_network(chain_state, &token_network_address)?.token_address;
+    let token_network_registry_address =
+        views::get_token_network_registry_by_token_network_address(chain_state, token_network_address)?.address;
     let reveal_timeout = U256::from(constants::DEFAULT_REVEAL_TIMEOUT);
     let open_transaction = TransactionExecutionStatus {
         started_block_number: Some(U64::from(0)),
@@ -97,17 +119,157 @@ fn create_channel_opened_state_change(chain_state: &ChainState, base_event: Even
     ))
 }
 
+fn create_channel_deposit_state_change(chain_state: &ChainState, base_event: Event, log: &Log) -> Option<StateChange> {
+    let channel_identifier = match base_event.data[0] {
+        Token::Uint(identifier) => identifier,
+        _ => U256::zero(),
+    };
+    let participant = match base_event.data[1] {
+        Token::Address(address) => address,
+        _ => Address::zero(),
+    };
+    let total_deposit = match base_event.data[2] {
+        Token::Uint(total_deposit) => total_deposit,
+        _ => U256::zero(),
+    };
+
+    let canonical_identifier = CanonicalIdentifier {
+        chain_identifier: u64::from(chain_state.chain_id.clone()),
+        token_network_address: log.address,
+        channel_identifier,
+    };
+    let deposit_transaction = TransactionChannelDeposit {
+        participant_address: participant,
+        contract_balance: total_deposit,
+        deposit_block_number: base_event.block_number,
+    };
+
+    Some(StateChange::ContractReceiveChannelDeposit(
+        ContractReceiveChannelDeposit {
+            transaction_hash: Some(base_event.transaction_hash),
+            canonical_identifier,
+            deposit_transaction,
+            block_number: base_event.block_number,
+            block_hash: base_event.block_hash,
+        },
+    ))
+}
+
+fn create_channel_closed_state_change(chain_state: &ChainState, base_event: Event, log: &Log) -> Option<StateChange> {
+    let channel_identifier = match base_event.data[0] {
+        Token::Uint(identifier) => identifier,
+        _ => U256::zero(),
+    };
+    let closing_participant = match base_event.data[1] {
+        Token::Address(address) => address,
+        _ => Address::zero(),
+    };
+
+    let canonical_identifier = CanonicalIdentifier {
+        chain_identifier: u64::from(chain_state.chain_id.clone()),
+        token_network_address: log.address,
+        channel_identifier,
+    };
+
+    Some(StateChange::ContractReceiveChannelClosed(
+        ContractReceiveChannelClosed {
+            transaction_hash: Some(base_event.transaction_hash),
+            transaction_from: closing_participant,
+            canonical_identifier,
+            block_number: base_event.block_number,
+            block_hash: base_event.block_hash,
+        },
+    ))
+}
+
+fn create_update_transfer_state_change(chain_state: &ChainState, base_event: Event, log: &Log) -> Option<StateChange> {
+    let channel_identifier = match base_event.data[0] {
+        Token::Uint(identifier) => identifier,
+        _ => U256::zero(),
+    };
+    let nonce = match base_event.data[2] {
+        Token::Uint(nonce) => nonce,
+        _ => U256::zero(),
+    };
+
+    let canonical_identifier = CanonicalIdentifier {
+        chain_identifier: u64::from(chain_state.chain_id.clone()),
+        token_network_address: log.address,
+        channel_identifier,
+    };
+
+    Some(StateChange::ContractReceiveUpdateTransfer(
+        ContractReceiveUpdateTransfer {
+            transaction_hash: Some(base_event.transaction_hash),
+            canonical_identifier,
+            nonce,
+            block_number: base_event.block_number,
+            block_hash: base_event.block_hash,
+        },
+    ))
+}
+
+fn create_channel_settled_state_change(chain_state: &ChainState, base_event: Event, log: &Log) -> Option<StateChange> {
+    let channel_identifier = match base_event.data[0] {
+        Token::Uint(identifier) => identifier,
+        _ => U256::zero(),
+    };
+
+    let canonical_identifier = CanonicalIdentifier {
+        chain_identifier: u64::from(chain_state.chain_id.clone()),
+        token_network_address: log.address,
+        channel_identifier,
+    };
+
+    Some(StateChange::ContractReceiveChannelSettled(
+        ContractReceiveChannelSettled {
+            transaction_hash: Some(base_event.transaction_hash),
+            canonical_identifier,
+            block_number: base_event.block_number,
+            block_hash: base_event.block_hash,
+        },
+    ))
+}
+
+fn create_secret_reveal_state_change(base_event: Event, log: &Log) -> Option<StateChange> {
+    let secrethash = match &base_event.data[0] {
+        Token::FixedBytes(secrethash) => H256::from_slice(secrethash),
+        _ => H256::zero(),
+    };
+    let secret = match &base_event.data[1] {
+        Token::FixedBytes(secret) => H256::from_slice(secret),
+        _ => H256::zero(),
+    };
+
+    Some(StateChange::ContractReceiveSecretReveal(
+        ContractReceiveSecretReveal::new(
+            base_event.transaction_hash,
+            log.address,
+            secrethash,
+            secret,
+            base_event.block_number,
+            base_event.block_hash,
+        ),
+    ))
+}
+
 pub fn log_to_blockchain_state_change(
     chain_state: &Option<ChainState>,
     contract_registry: &ContractRegistry,
     log: &Log,
+    our_channel: bool,
 ) -> Option<StateChange> {
     let base_event = contract_registry.log_to_event(log)?;
     let chain_state = chain_state.as_ref().unwrap();
 
     match base_event.name.as_ref() {
         "TokenNetworkCreated" => create_token_network_created_state_change(base_event, log),
-        "ChannelOpened" => create_channel_opened_state_change(&chain_state, base_event, log),
+        "ChannelOpened" => create_channel_opened_state_change(&chain_state, base_event, log, our_channel),
+        "ChannelNewDeposit" => create_channel_deposit_state_change(chain_state, base_event, log),
+        "ChannelClosed" => create_channel_closed_state_change(chain_state, base_event, log),
+        "NonClosingBalanceProofUpdated" => create_update_transfer_state_change(chain_state, base_event, log),
+        "ChannelSettled" => create_channel_settled_state_change(chain_state, base_event, log),
+        "SecretRevealed" => create_secret_reveal_state_change(base_event, log),
         &_ => None,
     }
 }
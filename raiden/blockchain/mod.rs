@@ -1,3 +1,4 @@
 pub mod contracts;
 pub mod events;
 pub mod helpers;
+pub mod syncer;
@@ -0,0 +1,101 @@
+use super::contracts::abi::ContractFilter;
+use futures::compat::Future01CompatExt;
+use std::cmp;
+use web3::types::{Address, Filter, Log, U256, U64};
+
+/// A fetched log. `our_channel` is set for `ChannelOpened` logs which the RPC node
+/// matched on our address as one of the channel participants.
+#[derive(Clone, Debug)]
+pub struct FetchedLog {
+    pub log: Log,
+    pub our_channel: bool,
+}
+
+impl FetchedLog {
+    /// Sort key ordering logs the way they were emitted on chain.
+    pub fn chain_order(&self) -> (Option<U64>, Option<U256>, bool) {
+        (self.log.block_number, self.log.log_index, self.our_channel)
+    }
+}
+
+/// Fetches the logs of contract filters in block ranges of at most `batch_size` blocks,
+/// since RPC providers reject log queries over large ranges.
+pub struct LogSyncer {
+    web3: web3::Web3<web3::transports::Http>,
+    batch_size: U64,
+    our_address: Address,
+}
+
+impl LogSyncer {
+    pub fn new(web3: web3::Web3<web3::transports::Http>, batch_size: U64, our_address: Address) -> LogSyncer {
+        LogSyncer {
+            web3,
+            batch_size,
+            our_address,
+        }
+    }
+
+    /// The next block range to sync on the way to `to_block`, or `None` if every filter
+    /// is synced up to it already. The range starts right after the filter which is the
+    /// furthest behind, so that no filter skips any block.
+    pub fn next_window(&self, filters: &[ContractFilter], to_block: U64) -> Option<(U64, U64)> {
+        let last_synced_block = filters
+            .iter()
+            .map(|filter| filter.last_synced_block)
+            .filter(|last_synced_block| *last_synced_block < to_block)
+            .min()?;
+        let from_block = last_synced_block + U64::from(1);
+        let window_to_block = cmp::min(to_block, from_block + self.batch_size - U64::from(1));
+        Some((from_block, window_to_block))
+    }
+
+    /// Fetches the logs of every filter which is behind `window_to_block` up to it, merged
+    /// in the order they were emitted on chain. The `ChannelOpened` logs of our channels
+    /// come right after the same log fetched by the token network filter.
+    pub async fn fetch_window_logs(
+        &self,
+        filters: &[ContractFilter],
+        window_to_block: U64,
+    ) -> Result<Vec<FetchedLog>, web3::Error> {
+        let mut logs = vec![];
+        for filter in filters
+            .iter()
+            .filter(|filter| filter.last_synced_block < window_to_block)
+        {
+            let from_block = filter.last_synced_block + U64::from(1);
+            logs.extend(
+                self.fetch_logs(filter, from_block, window_to_block)
+                    .await?
+                    .into_iter()
+                    .map(|log| FetchedLog {
+                        log,
+                        our_channel: false,
+                    }),
+            );
+            for participant_filter in filter.build_participant_filters(self.our_address, from_block, window_to_block) {
+                logs.extend(
+                    self.fetch(participant_filter)
+                        .await?
+                        .into_iter()
+                        .map(|log| FetchedLog { log, our_channel: true }),
+                );
+            }
+        }
+        logs.sort_by_key(FetchedLog::chain_order);
+
+        Ok(logs)
+    }
+
+    pub async fn fetch_logs(
+        &self,
+        filter: &ContractFilter,
+        from_block: U64,
+        to_block: U64,
+    ) -> Result<Vec<Log>, web3::Error> {
+        self.fetch(filter.build(from_block, to_block)).await
+    }
+
+    async fn fetch(&self, filter: Filter) -> Result<Vec<Log>, web3::Error> {
+        self.web3.eth().logs(filter).compat().await
+    }
+}
@@ -3,9 +3,11 @@ use ethsign::SecretKey;
 use rpassword;
 use slog::Logger;
 use std::collections::HashMap;
+use std::env;
 use std::io::{stdin, stdout, Write};
-use std::path::Path;
-use web3::types::Address;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use web3::types::{Address, U64};
 
 use crate::accounts::keystore;
 
@@ -13,9 +15,13 @@ use crate::accounts::keystore;
 pub struct Config<'a> {
     pub keystore_path: &'a Path,
     pub private_key: SecretKey,
+    pub datadir: PathBuf,
+    pub confirmation_blocks: U64,
+    pub sync_batch_size: U64,
 
     pub eth_http_rpc_endpoint: String,
-    pub eth_socket_rpc_endpoint: String,
+    pub eth_socket_rpc_endpoint: Option<String>,
+    pub block_polling_interval: Duration,
 }
 
 pub fn get_cli_app<'a, 'b>() -> App<'a, 'b> {
@@ -24,11 +30,22 @@ pub fn get_cli_app<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("chain-id")
                 .short("c")
                 .long("chain-id")
-                .possible_values(&["ropsten", "kovan", "goerli", "mainnet"])
                 .default_value("mainnet")
                 .required(true)
                 .takes_value(true)
-                .help("Specify the blockchain to run Raiden with"),
+                .help("Specify the blockchain to run Raiden with, either by name or by numeric chain id"),
+        )
+        .arg(
+            Arg::with_name("deployment-file")
+                .long("deployment-file")
+                .takes_value(true)
+                .help("Contracts deployment file to use instead of the bundled deployment data"),
+        )
+        .arg(
+            Arg::with_name("registry-address")
+                .long("registry-address")
+                .takes_value(true)
+                .help("Address of the TokenNetworkRegistry to use instead of the deployed one"),
         )
         .arg(
             Arg::with_name("eth-rpc-endpoint")
@@ -40,9 +57,14 @@ pub fn get_cli_app<'a, 'b>() -> App<'a, 'b> {
         .arg(
             Arg::with_name("eth-rpc-socket-endpoint")
                 .long("eth-rpc-socket-endpoint")
-                .required(true)
                 .takes_value(true)
-                .help("Specify the RPC endpoint to interact with"),
+                .help("Specify the RPC endpoint to subscribe to new blocks, blocks are polled over HTTP if omitted"),
+        )
+        .arg(
+            Arg::with_name("block-polling-interval")
+                .long("block-polling-interval")
+                .takes_value(true)
+                .help("Seconds between polls for new blocks without a socket endpoint, defaults to 5"),
         )
         .arg(
             Arg::with_name("keystore-path")
@@ -51,6 +73,24 @@ pub fn get_cli_app<'a, 'b>() -> App<'a, 'b> {
                 .required(true)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("datadir")
+                .long("datadir")
+                .takes_value(true)
+                .help("Directory for storing raiden data, defaults to ~/.raiden"),
+        )
+        .arg(
+            Arg::with_name("confirmation-blocks")
+                .long("confirmation-blocks")
+                .takes_value(true)
+                .help("Number of blocks on top of a block before its logs are applied, defaults to 5"),
+        )
+        .arg(
+            Arg::with_name("sync-batch-size")
+                .long("sync-batch-size")
+                .takes_value(true)
+                .help("Maximum number of blocks to fetch logs for in a single request, defaults to 1000"),
+        )
         .arg(
             Arg::with_name("verbosity")
                 .short("v")
@@ -60,6 +100,13 @@ pub fn get_cli_app<'a, 'b>() -> App<'a, 'b> {
         .subcommand(SubCommand::with_name("run").about("Run the raiden client"))
 }
 
+pub fn default_datadir() -> PathBuf {
+    match env::var("HOME") {
+        Ok(home) => Path::new(&home).join(".raiden"),
+        Err(_) => PathBuf::from(".raiden"),
+    }
+}
+
 pub fn prompt_key(keys: &HashMap<String, Address>) -> String {
     println!("Select key:");
     loop {
@@ -1 +1,18 @@
+use web3::types::H256;
+
 pub const DEFAULT_REVEAL_TIMEOUT: u16 = 50;
+pub const DEFAULT_NUMBER_OF_BLOCK_CONFIRMATIONS: u64 = 5;
+pub const DEFAULT_BLOCK_POLLING_INTERVAL_SECONDS: u64 = 5;
+pub const NEW_HEADS_TIMEOUT_SECONDS: u64 = 120;
+pub const RECONNECT_MIN_BACKOFF_SECONDS: u64 = 1;
+pub const RECONNECT_MAX_BACKOFF_SECONDS: u64 = 60;
+pub const NUMBER_OF_TRACKED_BLOCK_HASHES: u64 = 128;
+pub const DEFAULT_SYNC_BATCH_SIZE: u64 = 1000;
+pub const SNAPSHOT_STATE_CHANGES_COUNT: i64 = 500;
+pub const DEFAULT_WAIT_BEFORE_LOCK_REMOVAL: u64 = 2 * DEFAULT_NUMBER_OF_BLOCK_CONFIRMATIONS;
+pub const MAXIMUM_PENDING_TRANSFERS: usize = 160;
+/// keccak256 of no data, the locksroot of a channel end without pending locks.
+pub const LOCKSROOT_OF_NO_LOCKS: H256 = H256([
+    0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7, 0x03, 0xc0, 0xe5, 0x00, 0xb6,
+    0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa, 0xd8, 0x04, 0x5d, 0x85, 0xa4, 0x70,
+]);
@@ -1,21 +1,63 @@
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
+use web3::types::U64;
 
 use crate::transfer::{
-    event::TokenNetworkCreated,
+    event::{ChannelOpened, ChannelSettled, TokenNetworkCreated},
+    mediated_transfer::{
+        event::{
+            ContractSendSecretReveal, InvalidReceivedLockExpired, InvalidReceivedLockedTransfer, InvalidReceivedUnlock,
+            PaymentReceivedSuccess, PaymentSentFailed, PaymentSentSuccess, SendBalanceProof, SendLockExpired,
+            SendLockedTransfer, SendSecretRequest, SendSecretReveal, UnlockClaimFailed, UnlockClaimSuccess,
+            UnlockFailed, UnlockSuccess,
+        },
+        state_change::{
+            ActionInitInitiator, ActionInitMediator, ActionInitTarget, ReceiveLockExpired, ReceiveSecretRequest,
+            ReceiveSecretReveal, ReceiveUnlock,
+        },
+    },
     state_change::{
-        ActionInitChain, Block, ContractReceiveChannelOpened, ContractReceiveTokenNetworkCreated,
-        ContractReceiveTokenNetworkRegistry,
+        ActionInitChain, Block, ContractReceiveChannelClosed, ContractReceiveChannelDeposit,
+        ContractReceiveChannelOpened, ContractReceiveChannelSettled, ContractReceiveRouteNew,
+        ContractReceiveSecretReveal, ContractReceiveTokenNetworkCreated, ContractReceiveTokenNetworkRegistry,
+        ContractReceiveUpdateTransfer,
     },
 };
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum ChainID {
-    Mainnet = 1,
-    Ropsten = 3,
-    Rinkeby = 4,
-    Goerli = 5,
-    Kovan = 42,
+    Mainnet,
+    Ropsten,
+    Rinkeby,
+    Goerli,
+    Kovan,
+    Private(u64),
+}
+
+impl From<u64> for ChainID {
+    fn from(chain_id: u64) -> ChainID {
+        match chain_id {
+            1 => ChainID::Mainnet,
+            3 => ChainID::Ropsten,
+            4 => ChainID::Rinkeby,
+            5 => ChainID::Goerli,
+            42 => ChainID::Kovan,
+            chain_id => ChainID::Private(chain_id),
+        }
+    }
+}
+
+impl From<ChainID> for u64 {
+    fn from(chain_id: ChainID) -> u64 {
+        match chain_id {
+            ChainID::Mainnet => 1,
+            ChainID::Ropsten => 3,
+            ChainID::Rinkeby => 4,
+            ChainID::Goerli => 5,
+            ChainID::Kovan => 42,
+            ChainID::Private(chain_id) => chain_id,
+        }
+    }
 }
 
 impl FromStr for ChainID {
@@ -28,7 +70,7 @@ impl FromStr for ChainID {
             "rinkeby" => Ok(ChainID::Rinkeby),
             "goerli" => Ok(ChainID::Goerli),
             "kovan" => Ok(ChainID::Kovan),
-            _ => Err(()),
+            _ => s.parse::<u64>().map(ChainID::from).map_err(|_| ()),
         }
     }
 }
@@ -40,9 +82,57 @@ pub enum StateChange {
     ContractReceiveTokenNetworkRegistry(ContractReceiveTokenNetworkRegistry),
     ContractReceiveTokenNetworkCreated(ContractReceiveTokenNetworkCreated),
     ContractReceiveChannelOpened(ContractReceiveChannelOpened),
+    ContractReceiveRouteNew(ContractReceiveRouteNew),
+    ContractReceiveChannelDeposit(ContractReceiveChannelDeposit),
+    ContractReceiveChannelClosed(ContractReceiveChannelClosed),
+    ContractReceiveUpdateTransfer(ContractReceiveUpdateTransfer),
+    ContractReceiveChannelSettled(ContractReceiveChannelSettled),
+    ContractReceiveSecretReveal(ContractReceiveSecretReveal),
+    ActionInitInitiator(ActionInitInitiator),
+    ReceiveSecretRequest(ReceiveSecretRequest),
+    ReceiveSecretReveal(ReceiveSecretReveal),
+    ActionInitMediator(ActionInitMediator),
+    ReceiveUnlock(ReceiveUnlock),
+    ReceiveLockExpired(ReceiveLockExpired),
+    ActionInitTarget(ActionInitTarget),
+}
+
+impl StateChange {
+    /// Block number of the blockchain log this state change was decoded from.
+    pub fn log_block_number(&self) -> Option<U64> {
+        match self {
+            StateChange::ContractReceiveTokenNetworkCreated(state_change) => Some(state_change.block_number),
+            StateChange::ContractReceiveChannelOpened(state_change) => Some(state_change.block_number),
+            StateChange::ContractReceiveRouteNew(state_change) => Some(state_change.block_number),
+            StateChange::ContractReceiveChannelDeposit(state_change) => Some(state_change.block_number),
+            StateChange::ContractReceiveChannelClosed(state_change) => Some(state_change.block_number),
+            StateChange::ContractReceiveUpdateTransfer(state_change) => Some(state_change.block_number),
+            StateChange::ContractReceiveChannelSettled(state_change) => Some(state_change.block_number),
+            StateChange::ContractReceiveSecretReveal(state_change) => Some(state_change.block_number),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Event {
     TokenNetworkCreated(TokenNetworkCreated),
+    ChannelOpened(ChannelOpened),
+    ChannelSettled(ChannelSettled),
+    SendLockedTransfer(SendLockedTransfer),
+    SendSecretReveal(SendSecretReveal),
+    SendBalanceProof(SendBalanceProof),
+    SendLockExpired(SendLockExpired),
+    PaymentSentSuccess(PaymentSentSuccess),
+    PaymentSentFailed(PaymentSentFailed),
+    UnlockSuccess(UnlockSuccess),
+    UnlockFailed(UnlockFailed),
+    ContractSendSecretReveal(ContractSendSecretReveal),
+    UnlockClaimSuccess(UnlockClaimSuccess),
+    UnlockClaimFailed(UnlockClaimFailed),
+    InvalidReceivedLockedTransfer(InvalidReceivedLockedTransfer),
+    InvalidReceivedUnlock(InvalidReceivedUnlock),
+    InvalidReceivedLockExpired(InvalidReceivedLockExpired),
+    SendSecretRequest(SendSecretRequest),
+    PaymentReceivedSuccess(PaymentReceivedSuccess),
 }
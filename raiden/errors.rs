@@ -70,3 +70,21 @@ impl error::Error for ChannelError {
         None
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct StorageError {
+    pub msg: String,
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
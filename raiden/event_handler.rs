@@ -1,20 +1,76 @@
+use crate::blockchain::contracts;
 use crate::enums::Event;
+use crate::errors::{RaidenError, StorageError};
 use crate::service::RaidenService;
+use web3::types::U64;
 
 pub struct EventHandler {}
 
+/// A filter which could not be persisted leaves its event unhandled, it is retried on restart.
+fn filter_error(e: StorageError) -> RaidenError {
+    RaidenError {
+        msg: format!("Could not persist filter: {}", e),
+    }
+}
+
 impl EventHandler {
-    pub async fn handle_event(raiden: &RaidenService, event: Event) {
+    pub async fn handle_event(raiden: &RaidenService, event: Event) -> Result<(), RaidenError> {
         match event {
             Event::TokenNetworkCreated(event) => {
-                let token_network_address = event.token_network.address;
-                raiden.contracts_registry.create_contract_event_filters(
-                    "TokenNetwork".to_string(),
-                    token_network_address.into(),
-                    event.block_number.into(),
-                );
-                raiden.poll_filters().await;
+                // The token network cannot have emitted logs before the block it was created in.
+                raiden
+                    .install_filter(
+                        contracts::TOKEN_NETWORK,
+                        event.token_network.address,
+                        event.block_number.saturating_sub(U64::from(1)),
+                    )
+                    .map_err(filter_error)?;
+            }
+            Event::ChannelOpened(event) => {
+                // Deposits may be made in the same block the channel is opened in.
+                raiden
+                    .install_channel_filter(
+                        event.canonical_identifier.token_network_address,
+                        event.canonical_identifier.channel_identifier,
+                        event.block_number.saturating_sub(U64::from(1)),
+                    )
+                    .map_err(filter_error)?;
+            }
+            Event::ChannelSettled(event) => {
+                raiden
+                    .uninstall_filter(&(
+                        event.canonical_identifier.token_network_address,
+                        Some(event.canonical_identifier.channel_identifier),
+                    ))
+                    .map_err(filter_error)?;
+            }
+            Event::SendLockedTransfer(_)
+            | Event::SendSecretReveal(_)
+            | Event::SendBalanceProof(_)
+            | Event::SendLockExpired(_)
+            | Event::SendSecretRequest(_) => {
+                // Left unhandled until the node has a transport to send messages with.
+                return Err(RaidenError {
+                    msg: String::from("Sending messages is not supported yet"),
+                });
+            }
+            Event::ContractSendSecretReveal(_) => {
+                // Left unhandled until the node has contract proxies to send transactions with.
+                return Err(RaidenError {
+                    msg: String::from("Sending transactions is not supported yet"),
+                });
             }
+            Event::PaymentSentSuccess(_)
+            | Event::PaymentSentFailed(_)
+            | Event::UnlockSuccess(_)
+            | Event::UnlockFailed(_)
+            | Event::UnlockClaimSuccess(_)
+            | Event::UnlockClaimFailed(_)
+            | Event::InvalidReceivedLockedTransfer(_)
+            | Event::InvalidReceivedUnlock(_)
+            | Event::InvalidReceivedLockExpired(_)
+            | Event::PaymentReceivedSuccess(_) => {}
         }
+        Ok(())
     }
 }
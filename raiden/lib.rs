@@ -11,6 +11,7 @@ pub mod constants;
 pub mod enums;
 pub mod errors;
 pub mod event_handler;
+pub mod routing;
 pub mod service;
 pub mod state;
 pub mod storage;
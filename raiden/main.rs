@@ -4,13 +4,20 @@ extern crate slog_term;
 extern crate tokio;
 extern crate web3;
 
+use futures::compat::Future01CompatExt;
 use raiden::accounts::keystore;
+use raiden::blockchain::contracts;
+use raiden::blockchain::helpers::parse_address;
 //use raiden::api::http;
 use raiden::cli;
+use raiden::constants;
+use raiden::enums::ChainID;
 use raiden::service;
 use raiden::traits::{ToHTTPEndpoint, ToSocketEndpoint};
 use slog::Drain;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use web3::types::U64;
 
 #[tokio::main]
 async fn main() {
@@ -24,21 +31,88 @@ async fn main() {
     let matches = cli_app.get_matches();
 
     let chain_name = matches.value_of("chain-id").unwrap();
-    let chain_id = chain_name.parse().unwrap();
+    let chain_id: ChainID = match chain_name.parse() {
+        Ok(chain_id) => chain_id,
+        Err(_) => {
+            crit!(log, "Invalid chain id: {}", chain_name);
+            return;
+        }
+    };
+
+    let registry_address = match matches.value_of("registry-address") {
+        Some(address) => match parse_address(address.to_string()) {
+            Some(address) => Some(address),
+            None => {
+                crit!(log, "Invalid registry address: {}", address);
+                return;
+            }
+        },
+        None => None,
+    };
+    let deployment_file = matches.value_of("deployment-file").map(Path::new);
+    let contract_deployment = match contracts::load_contract_deployment(&chain_id, deployment_file, registry_address) {
+        Ok(contract_deployment) => contract_deployment,
+        Err(e) => {
+            crit!(log, "{}", e);
+            return;
+        }
+    };
 
     let eth_rpc_http_endpoint = matches.value_of("eth-rpc-endpoint").unwrap();
-    let eth_rpc_socket_endpoint = matches.value_of("eth-rpc-socket-endpoint").unwrap();
     let http_endpoint = eth_rpc_http_endpoint.to_http();
     if let Err(e) = http_endpoint {
         crit!(log, "Invalid RPC endpoint: {}", e);
         return;
     }
 
-    let socket_endpoint = eth_rpc_socket_endpoint.to_socket();
-    if let Err(e) = socket_endpoint {
-        crit!(log, "Invalid RPC endpoint: {}", e);
-        return;
-    }
+    let socket_endpoint = match matches.value_of("eth-rpc-socket-endpoint") {
+        Some(eth_rpc_socket_endpoint) => match eth_rpc_socket_endpoint.to_socket() {
+            Ok(socket_endpoint) => Some(socket_endpoint),
+            Err(e) => {
+                crit!(log, "Invalid RPC endpoint: {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let block_polling_interval = match matches.value_of("block-polling-interval") {
+        Some(block_polling_interval) => match block_polling_interval.parse::<u64>() {
+            Ok(block_polling_interval) if block_polling_interval > 0 => block_polling_interval,
+            _ => {
+                crit!(log, "Invalid block polling interval: {}", block_polling_interval);
+                return;
+            }
+        },
+        None => constants::DEFAULT_BLOCK_POLLING_INTERVAL_SECONDS,
+    };
+
+    let datadir = match matches.value_of("datadir") {
+        Some(datadir) => PathBuf::from(datadir),
+        None => cli::default_datadir(),
+    };
+
+    let confirmation_blocks = match matches.value_of("confirmation-blocks") {
+        Some(confirmation_blocks) => match confirmation_blocks.parse::<u64>() {
+            Ok(confirmation_blocks) => confirmation_blocks,
+            Err(_) => {
+                crit!(log, "Invalid number of confirmation blocks: {}", confirmation_blocks);
+                return;
+            }
+        },
+        None => constants::DEFAULT_NUMBER_OF_BLOCK_CONFIRMATIONS,
+    };
+
+    let sync_batch_size = match matches.value_of("sync-batch-size") {
+        Some(sync_batch_size) => match sync_batch_size.parse::<u64>() {
+            Ok(sync_batch_size) if sync_batch_size > 0 => sync_batch_size,
+            _ => {
+                crit!(log, "Invalid sync batch size: {}", sync_batch_size);
+                return;
+            }
+        },
+        None => constants::DEFAULT_SYNC_BATCH_SIZE,
+    };
 
     let keystore_path = Path::new(matches.value_of("keystore-path").unwrap());
     let keys = keystore::list_keys(keystore_path).unwrap();
@@ -50,14 +124,36 @@ async fn main() {
     let config = cli::Config {
         keystore_path: keystore_path,
         private_key: private_key,
+        datadir,
+        confirmation_blocks: U64::from(confirmation_blocks),
+        sync_batch_size: U64::from(sync_batch_size),
         eth_http_rpc_endpoint: http_endpoint.unwrap(),
-        eth_socket_rpc_endpoint: socket_endpoint.unwrap(),
+        eth_socket_rpc_endpoint: socket_endpoint,
+        block_polling_interval: Duration::from_secs(block_polling_interval),
     };
     let (eloop, http) = web3::transports::Http::new(&config.eth_http_rpc_endpoint).unwrap();
     eloop.into_remote();
     let web3 = web3::Web3::new(http);
 
-    let service = service::RaidenService::new(web3, chain_id, our_address, config.private_key.clone(), log.clone());
+    match web3.eth().chain_id().compat().await {
+        Ok(rpc_chain_id) => {
+            if ChainID::from(rpc_chain_id.low_u64()) != chain_id {
+                crit!(
+                    log,
+                    "Chain id {} of the RPC endpoint does not match the configured chain {:?}",
+                    rpc_chain_id,
+                    chain_id
+                );
+                return;
+            }
+        }
+        Err(e) => {
+            crit!(log, "Could not fetch chain id from the RPC endpoint: {}", e);
+            return;
+        }
+    }
+
+    let service = service::RaidenService::new(web3, chain_id, our_address, &config, contract_deployment, log.clone());
 
     service.initialize().await;
     service.start(config).await;
@@ -0,0 +1,314 @@
+use crate::transfer::channel;
+use crate::transfer::state::{ChannelEdgeStatus, ChannelState, RouteState, TokenNetworkState};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use web3::types::{Address, U256};
+
+type Path = Vec<Address>;
+
+/// Open channels of a token network which can be used to reach the target. Our own
+/// channels are only usable if they can still lock `amount` in a new transfer.
+struct Network {
+    adjacency: HashMap<Address, Vec<(Address, U256)>>,
+}
+
+impl Network {
+    fn new(token_network: &TokenNetworkState, our_address: Address, amount: U256) -> Network {
+        let mut adjacency: HashMap<Address, Vec<(Address, U256)>> = HashMap::new();
+
+        for (channel_identifier, edge) in token_network.network_graph.edges.iter() {
+            if edge.status != ChannelEdgeStatus::Opened {
+                continue;
+            }
+            if edge.partner_of(our_address).is_some() {
+                let channel_state = match token_network.channelidentifiers_to_channels.get(channel_identifier) {
+                    Some(channel_state) => channel_state,
+                    None => continue,
+                };
+                if channel_state.close_transaction.is_some() || get_capacity(channel_state) < amount {
+                    continue;
+                }
+            }
+            adjacency
+                .entry(edge.participant1)
+                .or_default()
+                .push((edge.participant2, *channel_identifier));
+            adjacency
+                .entry(edge.participant2)
+                .or_default()
+                .push((edge.participant1, *channel_identifier));
+        }
+
+        // Sorted neighbours keep the returned routes deterministic.
+        for neighbours in adjacency.values_mut() {
+            neighbours.sort();
+        }
+
+        Network { adjacency }
+    }
+
+    /// Breadth first search for the path with the fewest hops which avoids the
+    /// removed nodes and the removed edges.
+    fn shortest_path(
+        &self,
+        source: Address,
+        target: Address,
+        removed_nodes: &HashSet<Address>,
+        removed_edges: &HashSet<(Address, Address)>,
+    ) -> Option<Path> {
+        let mut predecessors: HashMap<Address, Address> = HashMap::new();
+        let mut visited: HashSet<Address> = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(source);
+        queue.push_back(source);
+
+        while let Some(node) = queue.pop_front() {
+            if node == target {
+                let mut path = vec![target];
+                let mut current = target;
+                while let Some(predecessor) = predecessors.get(&current) {
+                    path.push(*predecessor);
+                    current = *predecessor;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let neighbours = match self.adjacency.get(&node) {
+                Some(neighbours) => neighbours,
+                None => continue,
+            };
+            for (neighbour, _) in neighbours {
+                if visited.contains(neighbour)
+                    || removed_nodes.contains(neighbour)
+                    || removed_edges.contains(&(node, *neighbour))
+                {
+                    continue;
+                }
+                visited.insert(*neighbour);
+                predecessors.insert(*neighbour, node);
+                queue.push_back(*neighbour);
+            }
+        }
+
+        None
+    }
+
+    /// Yen's algorithm, returns up to `k` loop-free paths ordered by the number of hops.
+    fn k_shortest_paths(&self, source: Address, target: Address, k: usize) -> Vec<Path> {
+        let mut paths: Vec<Path> = vec![];
+        let mut candidates: Vec<Path> = vec![];
+
+        match self.shortest_path(source, target, &HashSet::new(), &HashSet::new()) {
+            Some(path) => paths.push(path),
+            None => return paths,
+        }
+
+        while paths.len() < k {
+            let previous_path = paths[paths.len() - 1].clone();
+            for spur_index in 0..previous_path.len() - 1 {
+                let spur_node = previous_path[spur_index];
+                let root_path = &previous_path[..=spur_index];
+
+                let mut removed_edges = HashSet::new();
+                for path in paths.iter() {
+                    if path.len() > spur_index + 1 && &path[..=spur_index] == root_path {
+                        removed_edges.insert((path[spur_index], path[spur_index + 1]));
+                        removed_edges.insert((path[spur_index + 1], path[spur_index]));
+                    }
+                }
+                let removed_nodes: HashSet<Address> = root_path[..spur_index].iter().cloned().collect();
+
+                if let Some(spur_path) = self.shortest_path(spur_node, target, &removed_nodes, &removed_edges) {
+                    let mut candidate = root_path[..spur_index].to_vec();
+                    candidate.extend(spur_path);
+                    if !paths.contains(&candidate) && !candidates.contains(&candidate) {
+                        candidates.push(candidate);
+                    }
+                }
+            }
+
+            if candidates.is_empty() {
+                break;
+            }
+            // Stable sort keeps the discovery order between paths of the same length.
+            candidates.sort_by_key(|candidate| candidate.len());
+            paths.push(candidates.remove(0));
+        }
+
+        paths
+    }
+
+    /// The channel to use between two adjacent nodes of a path.
+    fn channel_between(&self, from: Address, to: Address) -> Option<U256> {
+        self.adjacency
+            .get(&from)?
+            .iter()
+            .find(|(neighbour, _)| *neighbour == to)
+            .map(|(_, channel_identifier)| *channel_identifier)
+    }
+}
+
+/// What we can still send over one of our channels, after off-chain transfers and pending locks.
+fn get_capacity(channel_state: &ChannelState) -> U256 {
+    channel::get_distributable(&channel_state.our_state, &channel_state.partner_state)
+}
+
+/// Computes up to `k` loop-free routes from `our_address` to `target_address`, preferring
+/// routes with fewer hops and, among those, more known capacity in our channel.
+pub fn get_best_routes(
+    token_network: &TokenNetworkState,
+    our_address: Address,
+    target_address: Address,
+    amount: U256,
+    k: usize,
+) -> Vec<RouteState> {
+    if our_address == target_address || k == 0 {
+        return vec![];
+    }
+
+    let network = Network::new(token_network, our_address, amount);
+    let mut routes: Vec<(RouteState, U256)> = network
+        .k_shortest_paths(our_address, target_address, k)
+        .into_iter()
+        .filter_map(|path| {
+            let forward_channel_id = network.channel_between(path[0], path[1])?;
+            let capacity = token_network
+                .channelidentifiers_to_channels
+                .get(&forward_channel_id)
+                .map(get_capacity)
+                .unwrap_or_else(U256::zero);
+            Some((
+                RouteState {
+                    route: path,
+                    forward_channel_id,
+                },
+                capacity,
+            ))
+        })
+        .collect();
+
+    routes.sort_by_key(|(route, capacity)| (route.route.len(), Reverse(*capacity)));
+    routes.into_iter().map(|(route, _)| route).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::state::{
+        CanonicalIdentifier, HashTimeLockState, TransactionExecutionStatus, TransactionResult,
+    };
+    use web3::types::{H256, U64};
+
+    fn address(index: u64) -> Address {
+        Address::from_low_u64_be(index)
+    }
+
+    fn token_network(channels: &[(u64, u64, u64)], our_deposits: &[(u64, u64)]) -> TokenNetworkState {
+        let mut token_network = TokenNetworkState::new(address(100), address(101));
+        for (channel_identifier, participant1, participant2) in channels {
+            token_network.network_graph.add_channel(
+                U256::from(*channel_identifier),
+                address(*participant1),
+                address(*participant2),
+            );
+        }
+        for (channel_identifier, deposit) in our_deposits {
+            let edge = &token_network.network_graph.edges[&U256::from(*channel_identifier)];
+            let canonical_identifier = CanonicalIdentifier {
+                chain_identifier: 1,
+                token_network_address: token_network.address,
+                channel_identifier: U256::from(*channel_identifier),
+            };
+            let open_transaction = TransactionExecutionStatus {
+                started_block_number: None,
+                finished_block_number: Some(U64::from(1)),
+                result: Some(TransactionResult::SUCCESS),
+            };
+            let mut channel_state = ChannelState::new(
+                canonical_identifier,
+                token_network.token_address,
+                address(102),
+                edge.participant1,
+                edge.participant2,
+                U256::from(10),
+                U256::from(500),
+                open_transaction,
+            )
+            .unwrap();
+            channel_state.our_state.contract_balance = U256::from(*deposit);
+            token_network
+                .channelidentifiers_to_channels
+                .insert(U256::from(*channel_identifier), channel_state);
+        }
+        token_network
+    }
+
+    /// 1 is us and 5 the target: 1-2-5, 1-3-2-5, 1-3-4-5 and 1-2-3-4-5.
+    fn fixture() -> TokenNetworkState {
+        token_network(
+            &[(1, 1, 2), (2, 1, 3), (3, 2, 5), (4, 3, 4), (5, 4, 5), (6, 2, 3)],
+            &[(1, 100), (2, 100)],
+        )
+    }
+
+    fn paths(token_network: &TokenNetworkState, k: usize) -> Vec<Path> {
+        Network::new(token_network, address(1), U256::from(10)).k_shortest_paths(address(1), address(5), k)
+    }
+
+    #[test]
+    fn k_shortest_paths_are_loop_free() {
+        let paths = paths(&fixture(), 10);
+
+        assert_eq!(paths.len(), 4);
+        for path in paths.iter() {
+            let nodes: HashSet<&Address> = path.iter().collect();
+            assert_eq!(nodes.len(), path.len());
+            assert_eq!(path.first(), Some(&address(1)));
+            assert_eq!(path.last(), Some(&address(5)));
+        }
+    }
+
+    #[test]
+    fn k_shortest_paths_are_ordered_by_hops() {
+        let paths = paths(&fixture(), 10);
+
+        assert_eq!(paths[0], vec![address(1), address(2), address(5)]);
+        let hops: Vec<usize> = paths.iter().map(|path| path.len()).collect();
+        assert_eq!(hops, vec![3, 4, 4, 5]);
+    }
+
+    #[test]
+    fn k_shortest_paths_respects_k() {
+        assert_eq!(paths(&fixture(), 2).len(), 2);
+        assert_eq!(paths(&fixture(), 1), vec![vec![address(1), address(2), address(5)]]);
+    }
+
+    #[test]
+    fn best_routes_prefer_capacity_among_equal_hops() {
+        let token_network = token_network(&[(1, 1, 2), (2, 1, 3), (3, 2, 5), (4, 3, 5)], &[(1, 20), (2, 50)]);
+
+        let routes = get_best_routes(&token_network, address(1), address(5), U256::from(10), 3);
+
+        let forward_channels: Vec<U256> = routes.iter().map(|route| route.forward_channel_id).collect();
+        assert_eq!(forward_channels, vec![U256::from(2), U256::from(1)]);
+    }
+
+    #[test]
+    fn best_routes_skip_channels_without_distributable_balance() {
+        let mut token_network = fixture();
+        let pending_lock = HashTimeLockState::new(U256::from(95), U64::from(100), H256::from_low_u64_be(1));
+        token_network
+            .channelidentifiers_to_channels
+            .get_mut(&U256::from(1))
+            .unwrap()
+            .our_state
+            .secrethashes_to_lockedlocks
+            .insert(pending_lock.secrethash, pending_lock);
+
+        let routes = get_best_routes(&token_network, address(1), address(5), U256::from(10), 3);
+
+        assert!(!routes.is_empty());
+        assert!(routes.iter().all(|route| route.forward_channel_id == U256::from(2)));
+    }
+}
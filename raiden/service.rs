@@ -1,32 +1,47 @@
 use crate::{
     blockchain::contracts,
+    blockchain::contracts::abi::{ContractFilter, FilterID},
     blockchain::events,
-    cli,
-    enums::{ChainID, StateChange},
+    blockchain::syncer::{FetchedLog, LogSyncer},
+    cli, constants,
+    constants::NUMBER_OF_TRACKED_BLOCK_HASHES,
+    enums::{ChainID, Event, StateChange},
+    errors::StorageError,
     event_handler::EventHandler,
     state::{Result, StateManager},
     storage, transfer,
-    transfer::state::TokenNetworkRegistryState,
+    transfer::state::{ChainState, TokenNetworkRegistryState},
     transfer::state_change::{ActionInitChain, ContractReceiveTokenNetworkRegistry},
+    transfer::views,
 };
 use ethsign::SecretKey;
 use futures::{compat::Future01CompatExt, compat::Stream01CompatExt, future::BoxFuture, future::FutureExt};
 use rusqlite::Connection;
 use slog::Logger;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::File;
 use std::process;
+use std::result;
 use std::sync::RwLock;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::{self, stream::StreamExt};
 use web3::transports::WebSocket;
-use web3::types::{Address, BlockNumber, H256, U64};
+use web3::types::{Address, BlockHeader, BlockId, BlockNumber, H256, U256, U64};
 
 pub struct RaidenService {
     pub chain_id: ChainID,
     pub our_address: Address,
     pub secret_key: SecretKey,
+    pub confirmation_blocks: U64,
     pub web3: web3::Web3<web3::transports::Http>,
     pub contracts_registry: Arc<contracts::abi::ContractRegistry>,
+    pub contract_deployment: contracts::ContractDeployment,
+    log_syncer: LogSyncer,
     state_manager: Arc<RwLock<StateManager>>,
+    block_hashes: RwLock<BTreeMap<U64, H256>>,
+    _db_lock: File,
     log: Logger,
 }
 
@@ -35,10 +50,26 @@ impl RaidenService {
         w3: web3::Web3<web3::transports::Http>,
         chain_id: ChainID,
         our_address: Address,
-        secret_key: SecretKey,
+        config: &cli::Config,
+        contract_deployment: contracts::ContractDeployment,
         log: Logger,
     ) -> RaidenService {
-        let conn = match Connection::open("raiden.db") {
+        if contract_deployment.token_network_registry().is_none() {
+            crit!(log, "No TokenNetworkRegistry deployment found for chain {:?}", chain_id);
+            process::exit(1)
+        }
+
+        let contracts_version = contracts::get_contracts_version();
+        let database_path = storage::database_path(&config.datadir, our_address, &chain_id, &contracts_version);
+        let db_lock = match storage::lock_database(&database_path) {
+            Ok(db_lock) => db_lock,
+            Err(e) => {
+                crit!(log, "Could not lock database: {}", e);
+                process::exit(1)
+            }
+        };
+
+        let conn = match Connection::open(&database_path) {
             Ok(conn) => Arc::new(Mutex::new(conn)),
             Err(e) => {
                 crit!(log, "Could not connect to database: {}", e);
@@ -46,45 +77,54 @@ impl RaidenService {
             }
         };
 
-        if let Err(e) = storage::setup_database(&conn.lock().unwrap()) {
+        if let Err(e) = storage::setup_database(&mut conn.lock().unwrap()) {
             crit!(log, "Could not setup database: {}", e);
             process::exit(1)
         }
 
         let state_manager = StateManager::new(Arc::clone(&conn));
         let contracts_registry = contracts::abi::ContractRegistry::default();
+        let log_syncer = LogSyncer::new(w3.clone(), config.sync_batch_size, our_address);
         RaidenService {
             web3: w3,
             chain_id: chain_id,
             our_address: our_address,
-            secret_key: secret_key,
+            secret_key: config.private_key.clone(),
+            confirmation_blocks: config.confirmation_blocks,
             contracts_registry: Arc::new(contracts_registry),
+            contract_deployment,
+            log_syncer,
             state_manager: Arc::new(RwLock::new(state_manager)),
+            block_hashes: RwLock::new(BTreeMap::new()),
+            _db_lock: db_lock,
             log: log,
         }
     }
 
     pub async fn initialize(&self) {
-        let state_manager = self.state_manager.clone();
-        let mut initialize = false;
-        if let Err(_e) = state_manager.write().unwrap().restore_state() {
-            initialize = true;
-        }
+        let restored = match self.state_manager.write().unwrap().restore_state() {
+            Ok(restored) => restored,
+            Err(e) => {
+                crit!(self.log, "{}", e);
+                process::exit(1)
+            }
+        };
 
-        if initialize {
+        if !restored {
+            let token_network_registry_deployment = self.token_network_registry_deployment();
             let init_chain = ActionInitChain {
                 chain_id: self.chain_id.clone(),
-                block_number: U64::from(1),
+                block_number: token_network_registry_deployment.block_number,
                 our_address: self.our_address.clone(),
             };
             if let Err(e) = self.transition(StateChange::ActionInitChain(init_chain)).await {
                 panic!(format!("Could not initialize chain state: {}", e));
             }
 
-            let token_network_registry_address = contracts::get_token_network_registry_address();
-            let token_network_registry = TokenNetworkRegistryState::new(token_network_registry_address, vec![]);
+            let token_network_registry =
+                TokenNetworkRegistryState::new(token_network_registry_deployment.address, vec![]);
 
-            let last_log_block_number = U64::from(1);
+            let last_log_block_number = token_network_registry_deployment.block_number;
             let last_log_block_hash = H256::zero();
 
             let new_network_registry_state_change = ContractReceiveTokenNetworkRegistry::new(
@@ -104,8 +144,23 @@ impl RaidenService {
             }
         }
 
-        self.install_filters();
-        self.poll_filters().await;
+        let current_state = self.state_manager.read().unwrap().current_state.clone().unwrap();
+        debug!(self.log, "Resuming from block"; "number" => current_state.block_number.to_string());
+
+        match self.web3.eth().block_number().compat().await {
+            Ok(block_number) => {
+                let block_state_change = self.new_block_state_change(block_number);
+                if let Err(e) = self.transition(StateChange::Block(block_state_change)).await {
+                    warn!(self.log, "Failed to transition: {}", e);
+                }
+            }
+            Err(e) => warn!(self.log, "Could not fetch latest block: {}", e),
+        }
+
+        self.install_filters(&current_state);
+        self.check_tracked_block_hashes().await;
+        self.handle_unhandled_events().await;
+        self.sync_confirmed_logs().await;
     }
 
     pub async fn start(&self, config: cli::Config<'_>) {
@@ -115,73 +170,563 @@ impl RaidenService {
             self.state_manager.read().unwrap().current_state
         );
 
-        self.run_blocks_monitor(config.eth_socket_rpc_endpoint).await;
+        match config.eth_socket_rpc_endpoint {
+            Some(eth_socket_rpc_endpoint) => self.run_blocks_monitor(eth_socket_rpc_endpoint).await,
+            None => self.run_blocks_poller(config.block_polling_interval).await,
+        }
     }
 
-    fn install_filters(&self) {
-        let token_network_registry_address = contracts::get_token_network_registry_address();
-        self.contracts_registry.create_contract_event_filters(
-            "TokenNetworkRegistry".to_string(),
-            token_network_registry_address,
-            BlockNumber::Earliest,
+    fn token_network_registry_deployment(&self) -> &contracts::DeployedContract {
+        // Presence is checked when the service is created.
+        self.contract_deployment.token_network_registry().unwrap()
+    }
+
+    /// Installs the filters persisted by previous runs, then any missing filter for the
+    /// registry and the token networks known to `chain_state`.
+    fn install_filters(&self, chain_state: &ChainState) {
+        let filters = match self.state_manager.read().unwrap().filters() {
+            Ok(filters) => filters,
+            Err(e) => {
+                crit!(self.log, "Could not load filters: {}", e);
+                process::exit(1)
+            }
+        };
+        for filter in filters {
+            self.contracts_registry.create_filter(
+                filter.contract_name,
+                filter.address,
+                filter.channel_identifier,
+                filter.last_synced_block,
+            );
+        }
+
+        let token_network_registry_deployment = self.token_network_registry_deployment();
+        let last_synced_block = if chain_state.confirmed_block_number > token_network_registry_deployment.block_number {
+            chain_state.confirmed_block_number
+        } else {
+            token_network_registry_deployment
+                .block_number
+                .saturating_sub(U64::from(1))
+        };
+        let mut results = vec![self.install_filter(
+            contracts::TOKEN_NETWORK_REGISTRY,
+            token_network_registry_deployment.address,
+            last_synced_block,
+        )];
+
+        // Secrets registered on-chain unlock the locks of payments in progress.
+        if let Some(secret_registry_deployment) = self.contract_deployment.secret_registry() {
+            let last_synced_block = if chain_state.confirmed_block_number > secret_registry_deployment.block_number {
+                chain_state.confirmed_block_number
+            } else {
+                secret_registry_deployment.block_number.saturating_sub(U64::from(1))
+            };
+            results.push(self.install_filter(
+                contracts::SECRET_REGISTRY,
+                secret_registry_deployment.address,
+                last_synced_block,
+            ));
+        }
+
+        for token_network_registry in chain_state.identifiers_to_tokennetworkregistries.values() {
+            for (token_network_address, token_network) in
+                token_network_registry.tokennetworkaddresses_to_tokennetworks.iter()
+            {
+                results.push(self.install_filter(contracts::TOKEN_NETWORK, *token_network_address, last_synced_block));
+
+                // Channel events were synced along with the token network before channels had their own filters.
+                let token_network_last_synced_block = self
+                    .contracts_registry
+                    .filters
+                    .read()
+                    .unwrap()
+                    .get(&(*token_network_address, None))
+                    .map(|filter| filter.last_synced_block)
+                    .unwrap_or(last_synced_block);
+                for channel_identifier in token_network.channelidentifiers_to_channels.keys() {
+                    results.push(self.install_channel_filter(
+                        *token_network_address,
+                        *channel_identifier,
+                        token_network_last_synced_block,
+                    ));
+                }
+            }
+        }
+
+        for result in results {
+            if let Err(e) = result {
+                warn!(self.log, "Failed to store filter: {}", e);
+            }
+        }
+    }
+
+    /// Installs and persists a filter for `contract_address` unless it is filtered already.
+    /// A filter which could not be persisted stays installed until the node restarts.
+    pub fn install_filter(
+        &self,
+        contract_name: &str,
+        contract_address: Address,
+        last_synced_block: U64,
+    ) -> result::Result<(), StorageError> {
+        let filter = self.contracts_registry.create_contract_event_filter(
+            contract_name.to_string(),
+            contract_address,
+            last_synced_block,
+        );
+        match filter {
+            Some(filter) => self.state_manager.read().unwrap().store_filter(&filter),
+            None => Ok(()),
+        }
+    }
+
+    /// Installs and persists a filter for the events of one of our channels.
+    pub fn install_channel_filter(
+        &self,
+        token_network_address: Address,
+        channel_identifier: U256,
+        last_synced_block: U64,
+    ) -> result::Result<(), StorageError> {
+        let filter = self.contracts_registry.create_channel_event_filter(
+            token_network_address,
+            channel_identifier,
+            last_synced_block,
         );
+        match filter {
+            Some(filter) => self.state_manager.read().unwrap().store_filter(&filter),
+            None => Ok(()),
+        }
     }
 
-    pub async fn poll_filters(&self) {
-        let filters = self.contracts_registry.filters.read().unwrap().clone();
-        let current_state = self.state_manager.read().unwrap().current_state.clone();
-        let contracts_registry = &self.contracts_registry;
-        for (_, contract_filters) in filters.iter() {
-            for filter in contract_filters.values() {
-                let logs = self.web3.eth().logs((*filter).clone()).compat().await;
-                println!("Logs {:?}", logs);
-                if let Ok(logs) = logs {
-                    for log in logs {
-                        if let Some(state_change) =
-                            events::log_to_blockchain_state_change(&current_state, contracts_registry, &log)
-                        {
-                            debug!(self.log, "State transition {:#?}", state_change);
-                            let _ = self.transition(state_change).await;
-                        }
+    pub fn uninstall_filter(&self, filter_id: &FilterID) -> result::Result<(), StorageError> {
+        match self.contracts_registry.remove_filter(filter_id) {
+            Some(filter) => self.state_manager.read().unwrap().delete_filter(&filter),
+            None => Ok(()),
+        }
+    }
+
+    fn new_block_state_change(&self, block_number: U64) -> transfer::state_change::Block {
+        let confirmed_block_number = block_number.saturating_sub(self.confirmation_blocks);
+        transfer::state_change::Block::new(self.chain_id.clone(), block_number, confirmed_block_number)
+    }
+
+    /// Syncs every filter up to the confirmed block of the current chain state,
+    /// since only logs which are deep enough in the chain are turned into state changes.
+    pub async fn sync_confirmed_logs(&self) {
+        let confirmed_block_number = match &self.state_manager.read().unwrap().current_state {
+            Some(chain_state) => chain_state.confirmed_block_number,
+            None => return,
+        };
+        self.sync_logs(confirmed_block_number).await;
+    }
+
+    /// Applies the logs of every filter up to `to_block`, one window of blocks at a time.
+    /// Logs of all filters in a window are applied in chain order. Filters installed while
+    /// applying a window, e.g. for a newly created token network, have their logs of the
+    /// rest of the window merged in before anything else is applied.
+    async fn sync_logs(&self, to_block: U64) {
+        loop {
+            let filters: Vec<ContractFilter> = self
+                .contracts_registry
+                .filters
+                .read()
+                .unwrap()
+                .values()
+                .cloned()
+                .collect();
+            let (from_block, window_to_block) = match self.log_syncer.next_window(&filters, to_block) {
+                Some(window) => window,
+                None => return,
+            };
+            debug!(self.log, "Syncing logs"; "from" => from_block.to_string(), "to" => window_to_block.to_string());
+
+            let mut logs = match self.log_syncer.fetch_window_logs(&filters, window_to_block).await {
+                Ok(logs) => logs,
+                Err(e) => {
+                    warn!(self.log, "Failed to fetch logs: {}", e);
+                    return;
+                }
+            };
+            let mut filters = filters;
+            let mut index = 0;
+            while index < logs.len() {
+                let current_state = self.state_manager.read().unwrap().current_state.clone();
+                let fetched_log = &logs[index];
+                let log_id = fetched_log.log.transaction_hash.zip(fetched_log.log.log_index);
+                let recorded = match self.is_log_recorded(log_id) {
+                    Ok(recorded) => recorded,
+                    // Retried with the next block instead of risking a duplicate state change.
+                    Err(e) => {
+                        warn!(self.log, "Failed to look up log: {}", e);
+                        return;
+                    }
+                };
+                if !recorded {
+                    if let Some(state_change) = events::log_to_blockchain_state_change(
+                        &current_state,
+                        &self.contracts_registry,
+                        &fetched_log.log,
+                        fetched_log.our_channel,
+                    ) {
+                        debug!(self.log, "State transition {:#?}", state_change);
+                        let _ = self.transition_log(state_change, log_id).await;
+                    }
+                }
+                index += 1;
+
+                let new_filters = self.new_filters(&filters, window_to_block);
+                if new_filters.is_empty() {
+                    continue;
+                }
+                match self.log_syncer.fetch_window_logs(&new_filters, window_to_block).await {
+                    Ok(new_logs) => {
+                        logs.extend(new_logs);
+                        logs[index..].sort_by_key(FetchedLog::chain_order);
+                        filters.extend(new_filters);
+                    }
+                    // The new filters catch up in a later window instead.
+                    Err(e) => warn!(self.log, "Failed to fetch logs: {}", e),
+                }
+            }
+
+            for filter in filters
+                .iter()
+                .filter(|filter| filter.last_synced_block < window_to_block)
+            {
+                if let Some(filter) = self
+                    .contracts_registry
+                    .set_last_synced_block(&filter.id(), window_to_block)
+                {
+                    if let Err(e) = self.state_manager.read().unwrap().store_filter(&filter) {
+                        warn!(self.log, "Failed to store filter: {}", e);
                     }
                 }
             }
         }
     }
 
+    /// Whether the log was transitioned before, by a window which was synced again since the
+    /// node stopped before storing the position of its filters.
+    fn is_log_recorded(&self, log_id: Option<storage::LogID>) -> result::Result<bool, StorageError> {
+        match log_id {
+            Some(log_id) => self.state_manager.read().unwrap().is_log_recorded(log_id),
+            None => Ok(false),
+        }
+    }
+
+    /// Filters installed after `filters` were collected, which are behind `window_to_block`.
+    fn new_filters(&self, filters: &[ContractFilter], window_to_block: U64) -> Vec<ContractFilter> {
+        self.contracts_registry
+            .filters
+            .read()
+            .unwrap()
+            .values()
+            .filter(|filter| filter.last_synced_block < window_to_block)
+            .filter(|filter| filters.iter().all(|known_filter| known_filter.id() != filter.id()))
+            .cloned()
+            .collect()
+    }
+
+    /// Follows new heads over the socket endpoint for as long as the node runs. Whenever the
+    /// subscription fails, ends or stalls, it is re-established with an exponential backoff.
     pub async fn run_blocks_monitor(&self, eth_socket_rpc_endpoint: String) {
-        let (eloop, ws) = WebSocket::new(&eth_socket_rpc_endpoint).unwrap();
-        eloop.into_remote();
+        let min_backoff = Duration::from_secs(constants::RECONNECT_MIN_BACKOFF_SECONDS);
+        let max_backoff = Duration::from_secs(constants::RECONNECT_MAX_BACKOFF_SECONDS);
+        let mut backoff = min_backoff;
+        loop {
+            match self.follow_new_heads(&eth_socket_rpc_endpoint).await {
+                Ok(received_heads) => {
+                    if received_heads > 0 {
+                        backoff = min_backoff;
+                    }
+                    warn!(self.log, "New heads subscription ended");
+                }
+                Err(e) => warn!(self.log, "New heads subscription failed: {}", e),
+            }
+
+            warn!(self.log, "Reconnecting in {} seconds", backoff.as_secs());
+            tokio::time::delay_for(backoff).await;
+            backoff = cmp::min(backoff * 2, max_backoff);
+        }
+    }
+
+    /// Subscribes to new heads and handles them until the stream ends or no head arrives
+    /// within the timeout. Returns the number of heads which were received.
+    async fn follow_new_heads(&self, eth_socket_rpc_endpoint: &str) -> result::Result<u64, web3::Error> {
+        // Dropping the event loop handle closes the connection once we stop following.
+        let (_eloop, ws) = WebSocket::new(eth_socket_rpc_endpoint)?;
         let web3 = web3::Web3::new(ws);
-        let chain_id = self.chain_id.clone();
-        let log = self.log.clone();
-
-        let block_stream = web3.eth_subscribe().subscribe_new_heads().compat().await;
-        if let Ok(stream) = block_stream {
-            let mut stream = stream.compat();
-            while let Some(subscription) = stream.next().await {
-                if let Ok(subscription) = subscription {
-                    println!("{:?}", subscription);
-                    if let Some(block_number) = subscription.number {
-                        debug!(log, "Received block"; "number" => block_number.to_string());
-
-                        let block_state_change =
-                            transfer::state_change::Block::new(chain_id.clone(), block_number.into());
-
-                        let _ = self.transition(StateChange::Block(block_state_change)).await;
+
+        let mut stream = web3.eth_subscribe().subscribe_new_heads().compat().await?.compat();
+        let timeout = Duration::from_secs(constants::NEW_HEADS_TIMEOUT_SECONDS);
+        let mut received_heads = 0;
+        while let Ok(Some(header)) = tokio::time::timeout(timeout, stream.next()).await {
+            self.handle_new_head(header?).await;
+            received_heads += 1;
+        }
+
+        Ok(received_heads)
+    }
+
+    /// Polls `eth_blockNumber` over HTTP, for providers which offer no socket endpoint.
+    pub async fn run_blocks_poller(&self, polling_interval: Duration) {
+        loop {
+            match self.web3.eth().block_number().compat().await {
+                Ok(block_number) => self.handle_blocks_until(block_number).await,
+                Err(e) => warn!(self.log, "Could not fetch latest block: {}", e),
+            }
+            tokio::time::delay_for(polling_interval).await;
+        }
+    }
+
+    async fn handle_new_head(&self, header: BlockHeader) {
+        let (block_number, block_hash) = match (header.number, header.hash) {
+            (Some(block_number), Some(block_hash)) => (block_number, block_hash),
+            _ => return,
+        };
+        debug!(self.log, "Received block"; "number" => block_number.to_string());
+
+        self.handle_blocks_until(block_number.saturating_sub(U64::from(1)))
+            .await;
+        self.handle_block(block_number, block_hash, header.parent_hash).await;
+    }
+
+    /// Handles the blocks after the last applied block up to and including `block_number`
+    /// by fetching them over HTTP, so that no `Block` state change is skipped.
+    async fn handle_blocks_until(&self, block_number: U64) {
+        let last_block_number = match &self.state_manager.read().unwrap().current_state {
+            Some(chain_state) => chain_state.block_number,
+            None => return,
+        };
+
+        let mut next_block_number = last_block_number + U64::from(1);
+        while next_block_number <= block_number {
+            let block = self
+                .web3
+                .eth()
+                .block(BlockId::Number(BlockNumber::Number(next_block_number)))
+                .compat()
+                .await;
+            match block {
+                Ok(Some(block)) => match block.hash {
+                    Some(block_hash) => {
+                        self.handle_block(next_block_number, block_hash, block.parent_hash)
+                            .await
                     }
+                    None => return,
+                },
+                Ok(None) => return,
+                Err(e) => {
+                    warn!(self.log, "Could not fetch block {}: {}", next_block_number, e);
+                    return;
+                }
+            }
+            next_block_number += U64::from(1);
+        }
+    }
+
+    async fn handle_block(&self, block_number: U64, block_hash: H256, parent_hash: H256) {
+        let parent_block_number = block_number.saturating_sub(U64::from(1));
+        let known_parent_hash = self.block_hashes.read().unwrap().get(&parent_block_number).cloned();
+        if let Some(known_parent_hash) = known_parent_hash {
+            if known_parent_hash != parent_hash {
+                self.handle_reorg(parent_block_number).await;
+            }
+        }
+        self.track_block_hash(block_number, block_hash);
+
+        let block_state_change = self.new_block_state_change(block_number);
+        let _ = self.transition(StateChange::Block(block_state_change)).await;
+        self.sync_confirmed_logs().await;
+    }
+
+    fn track_block_hash(&self, block_number: U64, block_hash: H256) {
+        let mut block_hashes = self.block_hashes.write().unwrap();
+        block_hashes.insert(block_number, block_hash);
+
+        let oldest_tracked_block_number = block_number.saturating_sub(U64::from(NUMBER_OF_TRACKED_BLOCK_HASHES));
+        *block_hashes = block_hashes.split_off(&oldest_tracked_block_number);
+
+        let stored =
+            self.state_manager
+                .read()
+                .unwrap()
+                .store_block_hash(block_number, block_hash, oldest_tracked_block_number);
+        if let Err(e) = stored {
+            warn!(self.log, "Failed to store block hash: {}", e);
+        }
+    }
+
+    /// Loads the block hashes tracked before the node was stopped and checks the newest
+    /// of them against the chain, so that a reorganization while the node was down is detected.
+    async fn check_tracked_block_hashes(&self) {
+        let block_hashes = match self.state_manager.read().unwrap().block_hashes() {
+            Ok(block_hashes) => block_hashes,
+            Err(e) => {
+                warn!(self.log, "Failed to load block hashes: {}", e);
+                return;
+            }
+        };
+        let newest_block = block_hashes.last().cloned();
+        self.block_hashes.write().unwrap().extend(block_hashes);
+
+        let (block_number, block_hash) = match newest_block {
+            Some(newest_block) => newest_block,
+            None => return,
+        };
+        let canonical_block = self
+            .web3
+            .eth()
+            .block(BlockId::Number(BlockNumber::Number(block_number)))
+            .compat()
+            .await;
+        match canonical_block {
+            Ok(Some(canonical_block)) if canonical_block.hash == Some(block_hash) => {}
+            Ok(_) => self.handle_reorg(block_number).await,
+            Err(e) => warn!(self.log, "Could not fetch block {}: {}", block_number, e),
+        }
+    }
+
+    /// Walks back from `block_number` to the newest tracked block which is still part
+    /// of the canonical chain. If none of them is, the fork is assumed to be right
+    /// below the oldest tracked block.
+    async fn find_fork_block_number(&self, block_number: U64) -> U64 {
+        let tracked_blocks: Vec<(U64, H256)> = self
+            .block_hashes
+            .read()
+            .unwrap()
+            .range(..=block_number)
+            .rev()
+            .map(|(number, hash)| (*number, *hash))
+            .collect();
+
+        for (number, hash) in tracked_blocks.iter() {
+            let canonical_block = self
+                .web3
+                .eth()
+                .block(BlockId::Number(BlockNumber::Number(*number)))
+                .compat()
+                .await;
+            if let Ok(Some(canonical_block)) = canonical_block {
+                if canonical_block.hash == Some(*hash) {
+                    return *number;
+                }
+            }
+        }
+
+        match tracked_blocks.last() {
+            Some((oldest_block_number, _)) => oldest_block_number.saturating_sub(U64::from(1)),
+            None => block_number,
+        }
+    }
+
+    async fn handle_reorg(&self, block_number: U64) {
+        let fork_block_number = self.find_fork_block_number(block_number).await;
+        warn!(self.log, "Chain reorganization detected"; "fork_block" => fork_block_number.to_string());
+
+        self.block_hashes
+            .write()
+            .unwrap()
+            .split_off(&(fork_block_number + U64::from(1)));
+        if let Err(e) = self
+            .state_manager
+            .read()
+            .unwrap()
+            .delete_block_hashes_after(fork_block_number)
+        {
+            warn!(self.log, "Failed to delete block hashes: {}", e);
+        }
+
+        let confirmed_block_number = match &self.state_manager.read().unwrap().current_state {
+            Some(chain_state) => chain_state.confirmed_block_number,
+            None => return,
+        };
+        // Logs are only applied once confirmed, a reorg of unconfirmed blocks needs no recovery.
+        if fork_block_number >= confirmed_block_number {
+            return;
+        }
+
+        if let Err(e) = self.state_manager.write().unwrap().rollback(fork_block_number) {
+            crit!(self.log, "Could not roll back to block {}: {}", fork_block_number, e);
+            process::exit(1)
+        }
+        let chain_state = self.state_manager.read().unwrap().current_state.clone();
+        if let Some(chain_state) = chain_state {
+            self.uninstall_unknown_filters(&chain_state);
+        }
+        for filter in self.contracts_registry.rewind_filters(fork_block_number) {
+            if let Err(e) = self.state_manager.read().unwrap().store_filter(&filter) {
+                warn!(self.log, "Failed to store filter: {}", e);
+            }
+        }
+        self.sync_confirmed_logs().await;
+    }
+
+    /// Uninstalls the filters of token networks and channels which `chain_state` does not
+    /// know of, e.g. those installed for logs which were reorganized away.
+    fn uninstall_unknown_filters(&self, chain_state: &ChainState) {
+        let unknown_filter_ids: Vec<FilterID> = self
+            .contracts_registry
+            .filters
+            .read()
+            .unwrap()
+            .values()
+            .filter(|filter| filter.contract_name == contracts::TOKEN_NETWORK)
+            .map(|filter| filter.id())
+            .filter(|(token_network_address, channel_identifier)| {
+                match views::get_token_network(chain_state, token_network_address) {
+                    Some(token_network) => match channel_identifier {
+                        Some(channel_identifier) => !token_network
+                            .channelidentifiers_to_channels
+                            .contains_key(channel_identifier),
+                        None => false,
+                    },
+                    None => true,
                 }
+            })
+            .collect();
+        for filter_id in unknown_filter_ids {
+            if let Err(e) = self.uninstall_filter(&filter_id) {
+                warn!(self.log, "Failed to delete filter: {}", e);
             }
         }
     }
 
+    async fn handle_event(&self, event_id: storage::EventID, event: Event) {
+        if let Err(e) = EventHandler::handle_event(self, event).await {
+            warn!(self.log, "Failed to handle event: {}", e);
+            return;
+        }
+        if let Err(e) = self.state_manager.read().unwrap().mark_event_handled(event_id) {
+            warn!(self.log, "Failed to mark event as handled: {}", e);
+        }
+    }
+
+    async fn handle_unhandled_events(&self) {
+        let events = match self.state_manager.read().unwrap().unhandled_events() {
+            Ok(events) => events,
+            Err(e) => {
+                warn!(self.log, "Failed to load unhandled events: {}", e);
+                return;
+            }
+        };
+        for (event_id, event) in events {
+            debug!(self.log, "Retrying event {:?}", event);
+            self.handle_event(event_id, event).await;
+        }
+    }
+
     pub fn transition(&self, state_change: StateChange) -> BoxFuture<Result<bool>> {
-        let transition_result = StateManager::transition(self.state_manager.write().unwrap(), state_change);
+        self.transition_log(state_change, None)
+    }
+
+    /// Transitions a state change decoded from the log identified by `log_id`.
+    fn transition_log(&self, state_change: StateChange, log_id: Option<storage::LogID>) -> BoxFuture<'_, Result<bool>> {
+        let transition_result = StateManager::transition(self.state_manager.write().unwrap(), state_change, log_id);
         async move {
             match transition_result {
                 Ok(events) => {
-                    for event in events {
-                        EventHandler::handle_event(self, event).await;
+                    for (event_id, event) in events {
+                        self.handle_event(event_id, event).await;
                     }
                     Ok(true)
                 }
@@ -1,3 +1,5 @@
+use crate::blockchain::contracts::abi::ContractFilter;
+use crate::constants;
 use crate::enums::Event;
 use crate::enums::StateChange;
 use crate::errors;
@@ -6,6 +8,7 @@ use crate::transfer::chain::{self, ChainTransition};
 use crate::transfer::state::ChainState;
 use std::result;
 use std::sync::{Arc, Mutex, RwLockWriteGuard};
+use web3::types::{H256, U64};
 
 pub type Result<T> = result::Result<T, errors::StateTransitionError>;
 
@@ -22,30 +25,87 @@ impl StateManager {
         }
     }
 
-    pub fn restore_state(&self) -> result::Result<bool, errors::RaidenError> {
-        Err(errors::RaidenError {
-            msg: String::from("Invalid state"),
-        })
-    }
+    pub fn restore_state(&mut self) -> result::Result<bool, errors::RaidenError> {
+        let snapshot = match storage::get_latest_snapshot(&self.dbconn) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                return Err(errors::RaidenError {
+                    msg: format!("Could not restore state: {}", e),
+                })
+            }
+        };
+        let (snapshot_state_change_id, mut current_state) = match snapshot {
+            Some((state_change_id, chain_state)) => (state_change_id, Some(chain_state)),
+            None => (0, None),
+        };
 
-    fn dispatch(&mut self, state_change: StateChange) -> Result<Vec<Event>> {
-        let current_state = self.current_state.clone();
+        let state_changes = match storage::get_state_changes_after(&self.dbconn, snapshot_state_change_id) {
+            Ok(state_changes) => state_changes,
+            Err(e) => {
+                return Err(errors::RaidenError {
+                    msg: format!("Could not restore state: {}", e),
+                })
+            }
+        };
+        if current_state.is_none() && state_changes.is_empty() {
+            return Ok(false);
+        }
 
-        let transition: Result<ChainTransition> = chain::state_transition(current_state, state_change);
+        // Replay the log exactly as it was applied. State changes which failed to
+        // transition at the time left the state untouched, so they are skipped here too.
+        for (_, state_change) in state_changes {
+            if let Ok(transition) = chain::state_transition(current_state.clone(), state_change) {
+                current_state = Some(transition.new_state);
+            }
+        }
+        self.current_state = current_state;
+
+        Ok(true)
+    }
 
-        match transition {
-            Ok(transition_result) => {
-                self.current_state.replace(transition_result.new_state);
-                Ok(transition_result.events)
+    /// Discards every state change decoded from a log above `fork_block_number` and
+    /// rebuilds the current state without them.
+    pub fn rollback(&mut self, fork_block_number: U64) -> result::Result<(), errors::RaidenError> {
+        let orphaned_ids = match storage::get_state_change_ids_with_logs_after(&self.dbconn, fork_block_number) {
+            Ok(orphaned_ids) => orphaned_ids,
+            Err(e) => {
+                return Err(errors::RaidenError {
+                    msg: format!("Could not roll back state: {}", e),
+                })
             }
+        };
+        if orphaned_ids.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = storage::orphan_state_changes(&self.dbconn, &orphaned_ids) {
+            return Err(errors::RaidenError {
+                msg: format!("Could not roll back state: {}", e),
+            });
+        }
+        self.restore_state()?;
+
+        Ok(())
+    }
+
+    fn dispatch(&self, state_change: StateChange) -> Result<ChainTransition> {
+        let current_state = self.current_state.clone();
+
+        match chain::state_transition(current_state, state_change) {
+            Ok(transition_result) => Ok(transition_result),
             Err(e) => Err(errors::StateTransitionError {
                 msg: format!("Could not transition: {}", e),
             }),
         }
     }
 
-    fn store_state_change(&self, state_change: StateChange) -> Result<bool> {
-        match storage::store_state_change(&self.dbconn, state_change) {
+    fn store_state_change(
+        &self,
+        state_change: &StateChange,
+        log_id: Option<storage::LogID>,
+        events: &[Event],
+    ) -> Result<(storage::StateChangeID, Vec<storage::EventID>)> {
+        match storage::store_state_change(&self.dbconn, state_change, log_id, events) {
             Ok(result) => Ok(result),
             Err(e) => Err(errors::StateTransitionError {
                 msg: format!("Could not store state change: {}", e),
@@ -53,13 +113,83 @@ impl StateManager {
         }
     }
 
-    pub fn transition(mut manager: RwLockWriteGuard<StateManager>, state_change: StateChange) -> Result<Vec<Event>> {
-        match manager.store_state_change(state_change.clone()) {
-            Ok(result) => Ok(result),
-            Err(e) => Err(e),
-        }?;
-        let result = manager.dispatch(state_change.clone());
+    fn maybe_snapshot(&self, state_change_id: storage::StateChangeID) {
+        if state_change_id % constants::SNAPSHOT_STATE_CHANGES_COUNT != 0 {
+            return;
+        }
+        if let Some(current_state) = &self.current_state {
+            // A failed snapshot is not fatal, restoring will start from an older one
+            // and replay a longer tail of the log.
+            let _ = storage::store_snapshot(&self.dbconn, state_change_id, current_state);
+        }
+    }
+
+    pub fn mark_event_handled(&self, event_id: storage::EventID) -> result::Result<(), errors::StorageError> {
+        storage::mark_event_handled(&self.dbconn, event_id)
+    }
+
+    pub fn unhandled_events(&self) -> result::Result<Vec<(storage::EventID, Event)>, errors::StorageError> {
+        storage::get_unhandled_events(&self.dbconn)
+    }
+
+    pub fn store_filter(&self, filter: &ContractFilter) -> result::Result<(), errors::StorageError> {
+        let filter_sync_state = storage::FilterSyncState {
+            contract_name: filter.contract_name.clone(),
+            address: filter.address,
+            channel_identifier: filter.channel_identifier,
+            last_synced_block: filter.last_synced_block,
+        };
+        storage::store_filter_sync_state(&self.dbconn, &filter_sync_state)
+    }
+
+    pub fn delete_filter(&self, filter: &ContractFilter) -> result::Result<(), errors::StorageError> {
+        storage::delete_filter_sync_state(&self.dbconn, filter.address, filter.channel_identifier)
+    }
+
+    pub fn filters(&self) -> result::Result<Vec<storage::FilterSyncState>, errors::StorageError> {
+        storage::get_filter_sync_states(&self.dbconn)
+    }
+
+    pub fn store_block_hash(
+        &self,
+        block_number: U64,
+        block_hash: H256,
+        oldest_block_number: U64,
+    ) -> result::Result<(), errors::StorageError> {
+        storage::store_block_hash(&self.dbconn, block_number, block_hash, oldest_block_number)
+    }
+
+    pub fn delete_block_hashes_after(&self, block_number: U64) -> result::Result<(), errors::StorageError> {
+        storage::delete_block_hashes_after(&self.dbconn, block_number)
+    }
+
+    pub fn block_hashes(&self) -> result::Result<Vec<(U64, H256)>, errors::StorageError> {
+        storage::get_block_hashes(&self.dbconn)
+    }
+
+    pub fn is_log_recorded(&self, log_id: storage::LogID) -> result::Result<bool, errors::StorageError> {
+        storage::is_log_recorded(&self.dbconn, log_id)
+    }
+
+    /// Applies `state_change`, `log_id` identifies the log it was decoded from if any.
+    pub fn transition(
+        mut manager: RwLockWriteGuard<StateManager>,
+        state_change: StateChange,
+        log_id: Option<storage::LogID>,
+    ) -> Result<Vec<(storage::EventID, Event)>> {
+        // The new state is only applied once the state change and its events are stored,
+        // a state change which failed to transition is stored without events.
+        let transition = manager.dispatch(state_change.clone());
+        let events: &[Event] = match &transition {
+            Ok(transition_result) => &transition_result.events,
+            Err(_) => &[],
+        };
+        let (state_change_id, event_ids) = manager.store_state_change(&state_change, log_id, events)?;
+
+        let transition_result = transition?;
+        manager.current_state.replace(transition_result.new_state);
+        manager.maybe_snapshot(state_change_id);
 
-        result
+        Ok(event_ids.into_iter().zip(transition_result.events).collect())
     }
 }
@@ -0,0 +1,408 @@
+use rusqlite::types::Type;
+use rusqlite::NO_PARAMS;
+use rusqlite::{params, Connection, Error as SQLiteError, OptionalExtension, Result as SQLiteResult, Transaction};
+use serde_json::Value;
+
+use crate::errors::StorageError;
+
+type Migration = fn(&Transaction) -> SQLiteResult<()>;
+
+/// Migrations in the order they have to be applied. The schema version of a database
+/// is the number of migrations which have been applied to it, so entries must only
+/// ever be appended to this list.
+const MIGRATIONS: &[Migration] = &[
+    create_initial_schema,
+    add_confirmed_block_number,
+    add_orphaned_state_changes,
+    create_filters_table,
+    add_filter_channel_identifier,
+    drop_snapshots_without_network_graph,
+    create_block_hashes_table,
+    add_state_change_log_block_number,
+    add_state_change_log_id,
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+fn create_initial_schema(transaction: &Transaction) -> SQLiteResult<()> {
+    transaction.execute(
+        "CREATE TABLE IF NOT EXISTS state_changes (
+             identifier integer primary key autoincrement,
+             data json not null,
+             timestamp timestamp not null default CURRENT_TIMESTAMP
+         )",
+        NO_PARAMS,
+    )?;
+    transaction.execute(
+        "CREATE TABLE IF NOT EXISTS snapshots (
+             identifier integer primary key autoincrement,
+             statechange_id integer not null unique,
+             data json not null,
+             timestamp timestamp not null default CURRENT_TIMESTAMP,
+             foreign key(statechange_id) references state_changes(identifier)
+         )",
+        NO_PARAMS,
+    )?;
+    transaction.execute(
+        "CREATE TABLE IF NOT EXISTS events (
+             identifier integer primary key autoincrement,
+             source_statechange_id integer not null,
+             data json not null,
+             handled integer not null default 0,
+             timestamp timestamp not null default CURRENT_TIMESTAMP,
+             foreign key(source_statechange_id) references state_changes(identifier)
+         )",
+        NO_PARAMS,
+    )?;
+
+    Ok(())
+}
+
+/// Rewrites the JSON payload of every row in `table` with `upgrade`.
+/// Used by migrations which change the serialized shape of state changes, snapshots or events.
+fn upgrade_json_payloads<F>(transaction: &Transaction, table: &str, upgrade: F) -> SQLiteResult<()>
+where
+    F: Fn(Value) -> Value,
+{
+    let rows: Vec<(i64, String)> = transaction
+        .prepare(&format!("SELECT identifier, data FROM {}", table))?
+        .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<SQLiteResult<_>>()?;
+
+    for (identifier, data) in rows {
+        let payload: Value = serde_json::from_str(&data)
+            .map_err(|e| SQLiteError::FromSqlConversionFailure(1, Type::Text, Box::new(e)))?;
+        transaction.execute(
+            &format!("UPDATE {} SET data = ?1 WHERE identifier = ?2", table),
+            params![upgrade(payload).to_string(), identifier],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// `ChainState` and the `Block` state change started tracking the latest confirmed block.
+/// Anything stored before that treated every block as confirmed.
+fn add_confirmed_block_number(transaction: &Transaction) -> SQLiteResult<()> {
+    fn copy_block_number(object: &mut Value) {
+        if let Some(block_number) = object.get("block_number").cloned() {
+            object["confirmed_block_number"] = block_number;
+        }
+    }
+
+    upgrade_json_payloads(transaction, "state_changes", |mut state_change| {
+        if let Some(block) = state_change.get_mut("Block") {
+            copy_block_number(block);
+        }
+        state_change
+    })?;
+    upgrade_json_payloads(transaction, "snapshots", |mut chain_state| {
+        copy_block_number(&mut chain_state);
+        chain_state
+    })
+}
+
+/// State changes decoded from logs of blocks which were reorganized away are kept
+/// in the log for reference but flagged so that they are never replayed.
+fn add_orphaned_state_changes(transaction: &Transaction) -> SQLiteResult<()> {
+    transaction.execute(
+        "ALTER TABLE state_changes ADD COLUMN orphaned integer not null default 0",
+        NO_PARAMS,
+    )?;
+
+    Ok(())
+}
+
+/// Contract log filters remember the last block they were synced up to, so that
+/// syncing continues from there after a restart.
+fn create_filters_table(transaction: &Transaction) -> SQLiteResult<()> {
+    transaction.execute(
+        "CREATE TABLE IF NOT EXISTS filters (
+             address text primary key,
+             contract_name text not null,
+             last_synced_block integer not null
+         )",
+        NO_PARAMS,
+    )?;
+
+    Ok(())
+}
+
+/// Filters of our channels share the token network address with the token network
+/// filter, so filters are keyed by address and channel identifier. SQLite cannot change
+/// the primary key of a table, hence the copy.
+fn add_filter_channel_identifier(transaction: &Transaction) -> SQLiteResult<()> {
+    transaction.execute(
+        "CREATE TABLE filters_with_channels (
+             address text not null,
+             channel_identifier text not null default '',
+             contract_name text not null,
+             last_synced_block integer not null,
+             primary key(address, channel_identifier)
+         )",
+        NO_PARAMS,
+    )?;
+    transaction.execute(
+        "INSERT INTO filters_with_channels(address, contract_name, last_synced_block)
+         SELECT address, contract_name, last_synced_block FROM filters",
+        NO_PARAMS,
+    )?;
+    transaction.execute("DROP TABLE filters", NO_PARAMS)?;
+    transaction.execute("ALTER TABLE filters_with_channels RENAME TO filters", NO_PARAMS)?;
+
+    Ok(())
+}
+
+/// Token network graphs started tracking the channels of all participants. Snapshots
+/// taken before lack them, restoring without those snapshots replays the whole state
+/// change log and rebuilds the graphs on the way.
+fn drop_snapshots_without_network_graph(transaction: &Transaction) -> SQLiteResult<()> {
+    transaction.execute("DELETE FROM snapshots", NO_PARAMS)?;
+
+    Ok(())
+}
+
+/// Hashes of the latest blocks are kept across restarts, so that a reorganization
+/// which happened while the node was down is detected.
+fn create_block_hashes_table(transaction: &Transaction) -> SQLiteResult<()> {
+    transaction.execute(
+        "CREATE TABLE IF NOT EXISTS block_hashes (
+             block_number integer primary key,
+             block_hash text not null
+         )",
+        NO_PARAMS,
+    )?;
+
+    Ok(())
+}
+
+/// State changes decoded from logs carry the block number of their log in an indexed
+/// column, so that a reorganization finds the ones above the fork without reading the
+/// whole log.
+fn add_state_change_log_block_number(transaction: &Transaction) -> SQLiteResult<()> {
+    // The state changes decoded from logs at the time of this migration.
+    const LOG_STATE_CHANGES: &[&str] = &[
+        "ContractReceiveTokenNetworkCreated",
+        "ContractReceiveChannelOpened",
+        "ContractReceiveRouteNew",
+        "ContractReceiveChannelDeposit",
+        "ContractReceiveChannelClosed",
+        "ContractReceiveUpdateTransfer",
+        "ContractReceiveChannelSettled",
+        "ContractReceiveSecretReveal",
+    ];
+
+    transaction.execute(
+        "ALTER TABLE state_changes ADD COLUMN log_block_number integer",
+        NO_PARAMS,
+    )?;
+    transaction.execute(
+        "CREATE INDEX state_changes_log_block_number ON state_changes(log_block_number)",
+        NO_PARAMS,
+    )?;
+
+    let rows: Vec<(i64, String)> = transaction
+        .prepare("SELECT identifier, data FROM state_changes")?
+        .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<SQLiteResult<_>>()?;
+    for (identifier, data) in rows {
+        let state_change: Value = serde_json::from_str(&data)
+            .map_err(|e| SQLiteError::FromSqlConversionFailure(1, Type::Text, Box::new(e)))?;
+        let log_block_number = LOG_STATE_CHANGES
+            .iter()
+            .find_map(|name| state_change.get(name))
+            .and_then(|state_change| state_change.get("block_number"))
+            .and_then(|block_number| block_number.as_str())
+            .and_then(|block_number| i64::from_str_radix(block_number.trim_start_matches("0x"), 16).ok());
+        if let Some(log_block_number) = log_block_number {
+            transaction.execute(
+                "UPDATE state_changes SET log_block_number = ?1 WHERE identifier = ?2",
+                params![log_block_number, identifier],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// State changes decoded from logs carry the transaction hash and index of their log, so
+/// that logs fetched again after a restart are not stored twice. The log index is not part
+/// of the stored payloads, older state changes are left without one.
+fn add_state_change_log_id(transaction: &Transaction) -> SQLiteResult<()> {
+    transaction.execute(
+        "ALTER TABLE state_changes ADD COLUMN log_transaction_hash text",
+        NO_PARAMS,
+    )?;
+    transaction.execute("ALTER TABLE state_changes ADD COLUMN log_index integer", NO_PARAMS)?;
+    transaction.execute(
+        "CREATE INDEX state_changes_log_id ON state_changes(log_transaction_hash, log_index)",
+        NO_PARAMS,
+    )?;
+
+    Ok(())
+}
+
+fn get_version(conn: &Connection) -> SQLiteResult<u32> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+             version integer not null
+         )",
+        NO_PARAMS,
+    )?;
+    let version: Option<u32> = conn
+        .query_row("SELECT version FROM schema_version LIMIT 1", NO_PARAMS, |row| {
+            row.get(0)
+        })
+        .optional()?;
+
+    Ok(version.unwrap_or(0))
+}
+
+fn apply_migration(conn: &mut Connection, migration: Migration, version: u32) -> SQLiteResult<()> {
+    let transaction = conn.transaction()?;
+    migration(&transaction)?;
+    transaction.execute("DELETE FROM schema_version", NO_PARAMS)?;
+    transaction.execute("INSERT INTO schema_version(version) VALUES(?1)", params![version])?;
+    transaction.commit()
+}
+
+pub fn migrate(conn: &mut Connection) -> Result<(), StorageError> {
+    let current_version = get_version(conn).map_err(|e| StorageError {
+        msg: format!("Could not read schema version: {}", e),
+    })?;
+
+    if current_version > latest_version() {
+        return Err(StorageError {
+            msg: format!(
+                "Database schema version {} is newer than the supported version {}",
+                current_version,
+                latest_version()
+            ),
+        });
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current_version as usize) {
+        let version = index as u32 + 1;
+        apply_migration(conn, *migration, version).map_err(|e| StorageError {
+            msg: format!("Could not migrate database to version {}: {}", version, e),
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database_at_version(version: u32) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        get_version(&conn).unwrap();
+        for (index, migration) in MIGRATIONS.iter().enumerate().take(version as usize) {
+            apply_migration(&mut conn, *migration, index as u32 + 1).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn migrates_every_older_version_to_the_latest() {
+        for version in 0..=latest_version() {
+            let mut conn = database_at_version(version);
+            migrate(&mut conn).unwrap();
+            assert_eq!(get_version(&conn).unwrap(), latest_version());
+
+            let versions: u32 = conn
+                .query_row("SELECT COUNT(*) FROM schema_version", NO_PARAMS, |row| row.get(0))
+                .unwrap();
+            assert_eq!(versions, 1);
+            conn.execute(
+                "INSERT INTO filters(address, channel_identifier, contract_name, last_synced_block)
+                 VALUES('00', '1', 'TokenNetwork', 0)",
+                NO_PARAMS,
+            )
+            .unwrap();
+            conn.prepare("SELECT orphaned FROM state_changes").unwrap();
+        }
+    }
+
+    #[test]
+    fn upgrades_stored_payloads() {
+        let mut conn = database_at_version(1);
+        conn.execute(
+            "INSERT INTO state_changes(data) VALUES(?1)",
+            params![r#"{"Block":{"chain_id":"Mainnet","block_number":"0x10"}}"#],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO snapshots(statechange_id, data) VALUES(1, '{\"block_number\":\"0x10\"}')",
+            NO_PARAMS,
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+
+        let data: String = conn
+            .query_row("SELECT data FROM state_changes", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        let state_change: Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(state_change["Block"]["confirmed_block_number"], "0x10");
+        let snapshots: u32 = conn
+            .query_row("SELECT COUNT(*) FROM snapshots", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(snapshots, 0);
+    }
+
+    #[test]
+    fn indexes_the_block_number_of_log_state_changes() {
+        let mut conn = database_at_version(7);
+        for data in &[
+            r#"{"Block":{"chain_id":"Mainnet","block_number":"0x10","confirmed_block_number":"0x5"}}"#,
+            r#"{"ContractReceiveChannelClosed":{"transaction_hash":null,"block_number":"0x1a"}}"#,
+        ] {
+            conn.execute("INSERT INTO state_changes(data) VALUES(?1)", params![data])
+                .unwrap();
+        }
+        migrate(&mut conn).unwrap();
+
+        let log_block_numbers: Vec<Option<i64>> = conn
+            .prepare("SELECT log_block_number FROM state_changes ORDER BY identifier")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .collect::<SQLiteResult<_>>()
+            .unwrap();
+        assert_eq!(log_block_numbers, vec![None, Some(26)]);
+    }
+
+    #[test]
+    fn keeps_filters_when_keying_them_by_channel() {
+        let mut conn = database_at_version(4);
+        conn.execute(
+            "INSERT INTO filters(address, contract_name, last_synced_block) VALUES('aa', 'TokenNetwork', 7)",
+            NO_PARAMS,
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+
+        let (channel_identifier, last_synced_block): (String, i64) = conn
+            .query_row(
+                "SELECT channel_identifier, last_synced_block FROM filters WHERE address = 'aa'",
+                NO_PARAMS,
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(channel_identifier, "");
+        assert_eq!(last_synced_block, 7);
+    }
+
+    #[test]
+    fn refuses_a_newer_database() {
+        let mut conn = database_at_version(latest_version());
+        conn.execute("UPDATE schema_version SET version = ?1", params![latest_version() + 1])
+            .unwrap();
+
+        assert!(migrate(&mut conn).is_err());
+        assert_eq!(get_version(&conn).unwrap(), latest_version() + 1);
+    }
+}
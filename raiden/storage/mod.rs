@@ -1,29 +1,481 @@
 extern crate rusqlite;
 
+use fs2::FileExt;
+use rusqlite::params;
+use rusqlite::OptionalExtension;
 use rusqlite::NO_PARAMS;
 use rusqlite::{Connection, Result as SQLiteResult};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
 use std::result::Result;
 use std::sync::{Arc, Mutex};
+use web3::types::{Address, H256, U256, U64};
 
-use crate::enums::StateChange;
-use crate::errors::SerializationError;
+use crate::enums::{ChainID, Event, StateChange};
+use crate::errors::StorageError;
+use crate::transfer::state::ChainState;
 
-pub fn setup_database(conn: &Connection) -> SQLiteResult<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS state (
-             id integer primary key,
-             name text not null unique
-         )",
-        NO_PARAMS,
+pub mod migrations;
+
+pub type StateChangeID = i64;
+pub type EventID = i64;
+/// The transaction hash and log index identifying the log a state change was decoded from.
+pub type LogID = (H256, U256);
+
+/// Every account, chain and contracts version gets its own database so that nodes
+/// sharing a data directory never write into each other's state.
+pub fn database_path(datadir: &Path, our_address: Address, chain_id: &ChainID, contracts_version: &str) -> PathBuf {
+    datadir
+        .join(format!("node_{}", hex::encode(our_address.as_bytes())))
+        .join(format!("netid_{}", u64::from(chain_id.clone())))
+        .join(format!("contracts_{}", contracts_version))
+        .join("raiden.db")
+}
+
+/// Takes an exclusive lock next to the database file. The lock is held for as long as
+/// the returned file is kept open and is released by the OS if the process dies.
+pub fn lock_database(database_path: &Path) -> Result<File, StorageError> {
+    let database_dir = database_path.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(database_dir).map_err(|e| StorageError {
+        msg: format!("Could not create {}: {}", database_dir.display(), e),
+    })?;
+
+    let lock_path = database_dir.join("raiden.lock");
+    let lock_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .map_err(|e| StorageError {
+            msg: format!("Could not open {}: {}", lock_path.display(), e),
+        })?;
+    lock_file.try_lock_exclusive().map_err(|_| StorageError {
+        msg: format!("{} is in use by another raiden process", database_path.display()),
+    })?;
+
+    Ok(lock_file)
+}
+
+pub fn setup_database(conn: &mut Connection) -> Result<(), StorageError> {
+    migrations::migrate(conn)
+}
+
+fn insert_state_change(
+    conn: &mut Connection,
+    state_change: String,
+    log_block_number: Option<i64>,
+    log_id: Option<(String, i64)>,
+    events: Vec<String>,
+) -> SQLiteResult<(StateChangeID, Vec<EventID>)> {
+    let (log_transaction_hash, log_index) = log_id.unzip();
+    let transaction = conn.transaction()?;
+    transaction.execute(
+        "INSERT INTO state_changes(data, log_block_number, log_transaction_hash, log_index)
+         VALUES(?1, ?2, ?3, ?4)",
+        params![state_change, log_block_number, log_transaction_hash, log_index],
     )?;
+    let state_change_id = transaction.last_insert_rowid();
+    let mut event_ids = vec![];
+    for data in events {
+        transaction.execute(
+            "INSERT INTO events(source_statechange_id, data) VALUES(?1, ?2)",
+            params![state_change_id, data],
+        )?;
+        event_ids.push(transaction.last_insert_rowid());
+    }
+    transaction.commit()?;
 
-    Ok(())
+    Ok((state_change_id, event_ids))
 }
 
+/// Writes a state change along with the events it emitted in a single transaction,
+/// replaying the log does not emit events again so they must never be lost on their own.
+/// State changes decoded from a log are stored with its `log_id`, see `is_log_recorded`.
 pub fn store_state_change(
-    _conn: &Arc<Mutex<Connection>>,
-    state_change: StateChange,
-) -> Result<bool, SerializationError> {
-    let _serialized_state_change = serde_json::to_string(&state_change);
-    Ok(true)
+    conn: &Arc<Mutex<Connection>>,
+    state_change: &StateChange,
+    log_id: Option<LogID>,
+    events: &[Event],
+) -> Result<(StateChangeID, Vec<EventID>), StorageError> {
+    let serialized_state_change = serde_json::to_string(state_change).map_err(|e| StorageError {
+        msg: format!("Could not serialize state change: {}", e),
+    })?;
+    let serialized_events = events
+        .iter()
+        .map(|event| {
+            serde_json::to_string(event).map_err(|e| StorageError {
+                msg: format!("Could not serialize event: {}", e),
+            })
+        })
+        .collect::<Result<Vec<String>, StorageError>>()?;
+
+    let log_block_number = state_change
+        .log_block_number()
+        .map(|block_number| block_number.as_u64() as i64);
+
+    insert_state_change(
+        &mut conn.lock().unwrap(),
+        serialized_state_change,
+        log_block_number,
+        log_id
+            .map(|(transaction_hash, log_index)| (hex::encode(transaction_hash.as_bytes()), log_index.as_u64() as i64)),
+        serialized_events,
+    )
+    .map_err(|e| StorageError {
+        msg: format!("Could not write state change: {}", e),
+    })
+}
+
+/// Whether a state change was already stored for the log, which happens when a sync window
+/// is fetched again after the node stopped before storing how far its filters had synced.
+pub fn is_log_recorded(conn: &Arc<Mutex<Connection>>, log_id: LogID) -> Result<bool, StorageError> {
+    let (transaction_hash, log_index) = log_id;
+    conn.lock()
+        .unwrap()
+        .query_row(
+            "SELECT 1 FROM state_changes
+             WHERE log_transaction_hash = ?1 AND log_index = ?2 AND orphaned = 0",
+            params![hex::encode(transaction_hash.as_bytes()), log_index.as_u64() as i64],
+            |_| Ok(()),
+        )
+        .optional()
+        .map(|row| row.is_some())
+        .map_err(|e| StorageError {
+            msg: format!("Could not read state changes: {}", e),
+        })
+}
+
+/// Identifiers of the state changes which were decoded from logs of blocks above `block_number`.
+pub fn get_state_change_ids_with_logs_after(
+    conn: &Arc<Mutex<Connection>>,
+    block_number: U64,
+) -> Result<Vec<StateChangeID>, StorageError> {
+    let conn = conn.lock().unwrap();
+    let rows: SQLiteResult<Vec<StateChangeID>> = conn
+        .prepare(
+            "SELECT identifier FROM state_changes
+             WHERE log_block_number > ?1 AND orphaned = 0
+             ORDER BY identifier ASC",
+        )
+        .and_then(|mut statement| {
+            statement
+                .query_map(params![block_number.as_u64() as i64], |row| row.get(0))?
+                .collect()
+        });
+    rows.map_err(|e| StorageError {
+        msg: format!("Could not read state changes: {}", e),
+    })
+}
+
+pub fn get_state_changes_after(
+    conn: &Arc<Mutex<Connection>>,
+    state_change_id: StateChangeID,
+) -> Result<Vec<(StateChangeID, StateChange)>, StorageError> {
+    let conn = conn.lock().unwrap();
+    let rows: SQLiteResult<Vec<(StateChangeID, String)>> = conn
+        .prepare(
+            "SELECT identifier, data FROM state_changes
+             WHERE identifier > ?1 AND orphaned = 0
+             ORDER BY identifier ASC",
+        )
+        .and_then(|mut statement| {
+            statement
+                .query_map(params![state_change_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        });
+    let rows = rows.map_err(|e| StorageError {
+        msg: format!("Could not read state changes: {}", e),
+    })?;
+
+    rows.iter()
+        .map(|(state_change_id, data)| {
+            let state_change = serde_json::from_str(data).map_err(|e| StorageError {
+                msg: format!("Could not deserialize state change: {}", e),
+            })?;
+            Ok((*state_change_id, state_change))
+        })
+        .collect()
+}
+
+fn update_orphaned_state_changes(conn: &mut Connection, state_change_ids: &[StateChangeID]) -> SQLiteResult<()> {
+    let transaction = conn.transaction()?;
+    for state_change_id in state_change_ids {
+        transaction.execute(
+            "UPDATE state_changes SET orphaned = 1 WHERE identifier = ?1",
+            params![state_change_id],
+        )?;
+    }
+    // Snapshots taken after the first orphaned state change contain its effects.
+    if let Some(first_orphaned_id) = state_change_ids.iter().min() {
+        transaction.execute(
+            "DELETE FROM snapshots WHERE statechange_id >= ?1",
+            params![first_orphaned_id],
+        )?;
+    }
+    transaction.commit()
+}
+
+pub fn orphan_state_changes(
+    conn: &Arc<Mutex<Connection>>,
+    state_change_ids: &[StateChangeID],
+) -> Result<(), StorageError> {
+    update_orphaned_state_changes(&mut conn.lock().unwrap(), state_change_ids).map_err(|e| StorageError {
+        msg: format!("Could not orphan state changes: {}", e),
+    })
+}
+
+pub fn store_snapshot(
+    conn: &Arc<Mutex<Connection>>,
+    state_change_id: StateChangeID,
+    chain_state: &ChainState,
+) -> Result<(), StorageError> {
+    let serialized_state = serde_json::to_string(chain_state).map_err(|e| StorageError {
+        msg: format!("Could not serialize chain state: {}", e),
+    })?;
+
+    conn.lock()
+        .unwrap()
+        .execute(
+            "INSERT INTO snapshots(statechange_id, data) VALUES(?1, ?2)",
+            params![state_change_id, serialized_state],
+        )
+        .map_err(|e| StorageError {
+            msg: format!("Could not write snapshot: {}", e),
+        })?;
+
+    Ok(())
+}
+
+pub fn get_latest_snapshot(conn: &Arc<Mutex<Connection>>) -> Result<Option<(StateChangeID, ChainState)>, StorageError> {
+    let row: Option<(StateChangeID, String)> = conn
+        .lock()
+        .unwrap()
+        .query_row(
+            "SELECT statechange_id, data FROM snapshots ORDER BY statechange_id DESC LIMIT 1",
+            NO_PARAMS,
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| StorageError {
+            msg: format!("Could not read snapshot: {}", e),
+        })?;
+
+    match row {
+        Some((state_change_id, data)) => {
+            let chain_state = serde_json::from_str(&data).map_err(|e| StorageError {
+                msg: format!("Could not deserialize snapshot: {}", e),
+            })?;
+            Ok(Some((state_change_id, chain_state)))
+        }
+        None => Ok(None),
+    }
+}
+
+pub fn mark_event_handled(conn: &Arc<Mutex<Connection>>, event_id: EventID) -> Result<(), StorageError> {
+    conn.lock()
+        .unwrap()
+        .execute("UPDATE events SET handled = 1 WHERE identifier = ?1", params![event_id])
+        .map_err(|e| StorageError {
+            msg: format!("Could not mark event as handled: {}", e),
+        })?;
+
+    Ok(())
+}
+
+pub fn get_unhandled_events(conn: &Arc<Mutex<Connection>>) -> Result<Vec<(EventID, Event)>, StorageError> {
+    let conn = conn.lock().unwrap();
+    let rows: SQLiteResult<Vec<(EventID, String)>> = conn
+        .prepare(
+            "SELECT events.identifier, events.data FROM events
+             JOIN state_changes ON state_changes.identifier = events.source_statechange_id
+             WHERE events.handled = 0 AND state_changes.orphaned = 0
+             ORDER BY events.identifier ASC",
+        )
+        .and_then(|mut statement| {
+            statement
+                .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        });
+    let rows = rows.map_err(|e| StorageError {
+        msg: format!("Could not read events: {}", e),
+    })?;
+
+    rows.iter()
+        .map(|(event_id, data)| {
+            let event = serde_json::from_str(data).map_err(|e| StorageError {
+                msg: format!("Could not deserialize event: {}", e),
+            })?;
+            Ok((*event_id, event))
+        })
+        .collect()
+}
+
+/// Sync progress of a contract filter as persisted in the `filters` table.
+pub struct FilterSyncState {
+    pub contract_name: String,
+    pub address: Address,
+    pub channel_identifier: Option<U256>,
+    pub last_synced_block: U64,
+}
+
+fn encode_channel_identifier(channel_identifier: Option<U256>) -> String {
+    channel_identifier
+        .map(|channel_identifier| channel_identifier.to_string())
+        .unwrap_or_default()
+}
+
+pub fn store_filter_sync_state(
+    conn: &Arc<Mutex<Connection>>,
+    filter_sync_state: &FilterSyncState,
+) -> Result<(), StorageError> {
+    conn.lock()
+        .unwrap()
+        .execute(
+            "INSERT OR REPLACE INTO filters(address, channel_identifier, contract_name, last_synced_block)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![
+                hex::encode(filter_sync_state.address.as_bytes()),
+                encode_channel_identifier(filter_sync_state.channel_identifier),
+                filter_sync_state.contract_name,
+                filter_sync_state.last_synced_block.as_u64() as i64
+            ],
+        )
+        .map_err(|e| StorageError {
+            msg: format!("Could not write filter: {}", e),
+        })?;
+
+    Ok(())
+}
+
+pub fn delete_filter_sync_state(
+    conn: &Arc<Mutex<Connection>>,
+    address: Address,
+    channel_identifier: Option<U256>,
+) -> Result<(), StorageError> {
+    conn.lock()
+        .unwrap()
+        .execute(
+            "DELETE FROM filters WHERE address = ?1 AND channel_identifier = ?2",
+            params![
+                hex::encode(address.as_bytes()),
+                encode_channel_identifier(channel_identifier)
+            ],
+        )
+        .map_err(|e| StorageError {
+            msg: format!("Could not delete filter: {}", e),
+        })?;
+
+    Ok(())
+}
+
+pub fn get_filter_sync_states(conn: &Arc<Mutex<Connection>>) -> Result<Vec<FilterSyncState>, StorageError> {
+    let conn = conn.lock().unwrap();
+    let rows: SQLiteResult<Vec<(String, String, String, i64)>> = conn
+        .prepare("SELECT address, channel_identifier, contract_name, last_synced_block FROM filters")
+        .and_then(|mut statement| {
+            statement
+                .query_map(NO_PARAMS, |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })?
+                .collect()
+        });
+    let rows = rows.map_err(|e| StorageError {
+        msg: format!("Could not read filters: {}", e),
+    })?;
+
+    rows.into_iter()
+        .map(|(address, channel_identifier, contract_name, last_synced_block)| {
+            let address = hex::decode(&address)
+                .ok()
+                .filter(|address| address.len() == 20)
+                .ok_or_else(|| StorageError {
+                    msg: format!("Invalid filter address {}", address),
+                })?;
+            let channel_identifier = if channel_identifier.is_empty() {
+                None
+            } else {
+                Some(U256::from_dec_str(&channel_identifier).map_err(|_| StorageError {
+                    msg: format!("Invalid filter channel identifier {}", channel_identifier),
+                })?)
+            };
+            Ok(FilterSyncState {
+                contract_name,
+                address: Address::from_slice(&address),
+                channel_identifier,
+                last_synced_block: U64::from(last_synced_block as u64),
+            })
+        })
+        .collect()
+}
+
+fn insert_block_hash(
+    conn: &mut Connection,
+    block_number: U64,
+    block_hash: H256,
+    oldest_block_number: U64,
+) -> SQLiteResult<()> {
+    let transaction = conn.transaction()?;
+    transaction.execute(
+        "INSERT OR REPLACE INTO block_hashes(block_number, block_hash) VALUES(?1, ?2)",
+        params![block_number.as_u64() as i64, hex::encode(block_hash.as_bytes())],
+    )?;
+    transaction.execute(
+        "DELETE FROM block_hashes WHERE block_number < ?1",
+        params![oldest_block_number.as_u64() as i64],
+    )?;
+    transaction.commit()
+}
+
+/// Stores the hash of `block_number` and forgets the hashes of blocks older than `oldest_block_number`.
+pub fn store_block_hash(
+    conn: &Arc<Mutex<Connection>>,
+    block_number: U64,
+    block_hash: H256,
+    oldest_block_number: U64,
+) -> Result<(), StorageError> {
+    insert_block_hash(&mut conn.lock().unwrap(), block_number, block_hash, oldest_block_number).map_err(|e| {
+        StorageError {
+            msg: format!("Could not write block hash: {}", e),
+        }
+    })
+}
+
+pub fn delete_block_hashes_after(conn: &Arc<Mutex<Connection>>, block_number: U64) -> Result<(), StorageError> {
+    conn.lock()
+        .unwrap()
+        .execute(
+            "DELETE FROM block_hashes WHERE block_number > ?1",
+            params![block_number.as_u64() as i64],
+        )
+        .map_err(|e| StorageError {
+            msg: format!("Could not delete block hashes: {}", e),
+        })?;
+
+    Ok(())
+}
+
+pub fn get_block_hashes(conn: &Arc<Mutex<Connection>>) -> Result<Vec<(U64, H256)>, StorageError> {
+    let conn = conn.lock().unwrap();
+    let rows: SQLiteResult<Vec<(i64, String)>> = conn
+        .prepare("SELECT block_number, block_hash FROM block_hashes ORDER BY block_number ASC")
+        .and_then(|mut statement| {
+            statement
+                .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        });
+    let rows = rows.map_err(|e| StorageError {
+        msg: format!("Could not read block hashes: {}", e),
+    })?;
+
+    rows.into_iter()
+        .map(|(block_number, block_hash)| {
+            let block_hash = hex::decode(&block_hash)
+                .ok()
+                .filter(|block_hash| block_hash.len() == 32)
+                .ok_or_else(|| StorageError {
+                    msg: format!("Invalid block hash {}", block_hash),
+                })?;
+            Ok((U64::from(block_number as u64), H256::from_slice(&block_hash)))
+        })
+        .collect()
 }
@@ -2,9 +2,13 @@ use crate::enums::{Event, StateChange};
 use crate::errors::StateTransitionError;
 use crate::transfer::{
     event,
-    state::{ChainState, TokenNetworkRegistryState, TokenNetworkState},
+    mediated_transfer::{initiator, mediator, state_change as mediated_state_change, target},
+    state::{
+        ChainState, InitiatorTask, MediatorTask, TargetTask, TokenNetworkRegistryState, TokenNetworkState, TransferTask,
+    },
     state_change, token_network, views,
 };
+use web3::types::{Address, H256};
 
 pub struct ChainTransition {
     pub new_state: ChainState,
@@ -24,14 +28,314 @@ fn handle_action_init_chain(
     })
 }
 
+fn get_token_network_mut<'a>(
+    chain_state: &'a mut ChainState,
+    token_network_address: &Address,
+) -> Option<&'a mut TokenNetworkState> {
+    chain_state
+        .identifiers_to_tokennetworkregistries
+        .values_mut()
+        .find_map(|registry| {
+            registry
+                .tokennetworkaddresses_to_tokennetworks
+                .get_mut(token_network_address)
+        })
+}
+
+/// Hands `state_change` to the task of the payment locked with `secrethash`, if any.
+/// The task is dropped once it completed, and left as it was if it failed to transition.
+fn subdispatch_to_paymenttask(
+    chain_state: &mut ChainState,
+    state_change: StateChange,
+    secrethash: H256,
+) -> Result<Vec<Event>, StateTransitionError> {
+    let block_number = chain_state.block_number;
+    let task = match chain_state.payment_mapping.secrethashes_to_task.get(&secrethash) {
+        Some(task) => task.clone(),
+        None => return Ok(vec![]),
+    };
+
+    let (new_task, events) = match task {
+        TransferTask::Initiator(task) => {
+            let token_network_address = task.token_network_address;
+            match get_token_network_mut(chain_state, &token_network_address) {
+                Some(token_network_state) => {
+                    let transition = initiator::state_transition(
+                        Some(task.manager_state),
+                        state_change,
+                        &mut token_network_state.channelidentifiers_to_channels,
+                        block_number,
+                    )?;
+                    let new_task = transition.new_state.map(|manager_state| {
+                        TransferTask::Initiator(InitiatorTask {
+                            token_network_address,
+                            manager_state,
+                        })
+                    });
+                    (new_task, transition.events)
+                }
+                None => (None, vec![]),
+            }
+        }
+        TransferTask::Mediator(task) => {
+            let token_network_address = task.token_network_address;
+            match get_token_network_mut(chain_state, &token_network_address) {
+                Some(token_network_state) => {
+                    let transition = mediator::state_transition(
+                        Some(task.mediator_state),
+                        state_change,
+                        &mut token_network_state.channelidentifiers_to_channels,
+                        block_number,
+                    )?;
+                    let new_task = transition.new_state.map(|mediator_state| {
+                        TransferTask::Mediator(MediatorTask {
+                            token_network_address,
+                            mediator_state,
+                        })
+                    });
+                    (new_task, transition.events)
+                }
+                None => (None, vec![]),
+            }
+        }
+        TransferTask::Target(task) => {
+            let canonical_identifier = task.canonical_identifier;
+            let channel_state = get_token_network_mut(chain_state, &canonical_identifier.token_network_address)
+                .and_then(|token_network_state| {
+                    token_network_state
+                        .channelidentifiers_to_channels
+                        .get_mut(&canonical_identifier.channel_identifier)
+                });
+            match channel_state {
+                Some(channel_state) => {
+                    let transition =
+                        target::state_transition(Some(task.target_state), state_change, channel_state, block_number)?;
+                    let new_task = transition.new_state.map(|target_state| {
+                        TransferTask::Target(TargetTask {
+                            canonical_identifier,
+                            target_state,
+                        })
+                    });
+                    (new_task, transition.events)
+                }
+                None => (None, vec![]),
+            }
+        }
+    };
+
+    let secrethashes_to_task = &mut chain_state.payment_mapping.secrethashes_to_task;
+    match new_task {
+        Some(new_task) => secrethashes_to_task.insert(secrethash, new_task),
+        None => secrethashes_to_task.remove(&secrethash),
+    };
+
+    Ok(events)
+}
+
 fn handle_new_block(
     mut chain_state: ChainState,
     state_change: state_change::Block,
 ) -> Result<ChainTransition, StateTransitionError> {
     chain_state.block_number = state_change.block_number;
+    chain_state.confirmed_block_number = state_change.confirmed_block_number;
+
+    let mut events = vec![];
+    let secrethashes: Vec<H256> = chain_state
+        .payment_mapping
+        .secrethashes_to_task
+        .keys()
+        .cloned()
+        .collect();
+    for secrethash in secrethashes {
+        // A task which fails to handle the block keeps its state, the block applies to the others regardless.
+        let state_change = StateChange::Block(state_change.clone());
+        if let Ok(task_events) = subdispatch_to_paymenttask(&mut chain_state, state_change, secrethash) {
+            events.extend(task_events);
+        }
+    }
+
     Ok(ChainTransition {
         new_state: chain_state,
-        events: vec![],
+        events,
+    })
+}
+
+/// Starts a payment, unless one locked with the same secrethash is already in progress.
+fn handle_init_initiator(
+    mut chain_state: ChainState,
+    state_change: mediated_state_change::ActionInitInitiator,
+) -> Result<ChainTransition, StateTransitionError> {
+    let secrethash = state_change.transfer.secrethash;
+    let token_network_address = state_change.transfer.token_network_address;
+    let block_number = chain_state.block_number;
+    if chain_state
+        .payment_mapping
+        .secrethashes_to_task
+        .contains_key(&secrethash)
+    {
+        return Ok(ChainTransition {
+            new_state: chain_state,
+            events: vec![],
+        });
+    }
+
+    let token_network_state = match get_token_network_mut(&mut chain_state, &token_network_address) {
+        Some(token_network_state) => token_network_state,
+        None => {
+            return Ok(ChainTransition {
+                new_state: chain_state,
+                events: vec![],
+            })
+        }
+    };
+    let transition = initiator::state_transition(
+        None,
+        StateChange::ActionInitInitiator(state_change),
+        &mut token_network_state.channelidentifiers_to_channels,
+        block_number,
+    )?;
+
+    if let Some(manager_state) = transition.new_state {
+        let task = TransferTask::Initiator(InitiatorTask {
+            token_network_address,
+            manager_state,
+        });
+        chain_state
+            .payment_mapping
+            .secrethashes_to_task
+            .insert(secrethash, task);
+    }
+
+    Ok(ChainTransition {
+        new_state: chain_state,
+        events: transition.events,
+    })
+}
+
+/// Starts mediating a transfer, unless its secrethash is already used by a payment in progress.
+fn handle_init_mediator(
+    mut chain_state: ChainState,
+    state_change: mediated_state_change::ActionInitMediator,
+) -> Result<ChainTransition, StateTransitionError> {
+    let secrethash = state_change.from_transfer.lock.secrethash;
+    let token_network_address = state_change
+        .from_transfer
+        .balance_proof
+        .canonical_identifier
+        .token_network_address;
+    let block_number = chain_state.block_number;
+    if chain_state
+        .payment_mapping
+        .secrethashes_to_task
+        .contains_key(&secrethash)
+    {
+        return Ok(ChainTransition {
+            new_state: chain_state,
+            events: vec![],
+        });
+    }
+
+    let token_network_state = match get_token_network_mut(&mut chain_state, &token_network_address) {
+        Some(token_network_state) => token_network_state,
+        None => {
+            return Ok(ChainTransition {
+                new_state: chain_state,
+                events: vec![],
+            })
+        }
+    };
+    let transition = mediator::state_transition(
+        None,
+        StateChange::ActionInitMediator(state_change),
+        &mut token_network_state.channelidentifiers_to_channels,
+        block_number,
+    )?;
+
+    if let Some(mediator_state) = transition.new_state {
+        let task = TransferTask::Mediator(MediatorTask {
+            token_network_address,
+            mediator_state,
+        });
+        chain_state
+            .payment_mapping
+            .secrethashes_to_task
+            .insert(secrethash, task);
+    }
+
+    Ok(ChainTransition {
+        new_state: chain_state,
+        events: transition.events,
+    })
+}
+
+/// Starts claiming a transfer addressed to us, unless its secrethash is already used by a payment in progress.
+fn handle_init_target(
+    mut chain_state: ChainState,
+    state_change: mediated_state_change::ActionInitTarget,
+) -> Result<ChainTransition, StateTransitionError> {
+    let secrethash = state_change.transfer.lock.secrethash;
+    let canonical_identifier = state_change.transfer.balance_proof.canonical_identifier.clone();
+    let block_number = chain_state.block_number;
+    if chain_state
+        .payment_mapping
+        .secrethashes_to_task
+        .contains_key(&secrethash)
+    {
+        return Ok(ChainTransition {
+            new_state: chain_state,
+            events: vec![],
+        });
+    }
+
+    let channel_state = get_token_network_mut(&mut chain_state, &canonical_identifier.token_network_address).and_then(
+        |token_network_state| {
+            token_network_state
+                .channelidentifiers_to_channels
+                .get_mut(&canonical_identifier.channel_identifier)
+        },
+    );
+    let channel_state = match channel_state {
+        Some(channel_state) => channel_state,
+        None => {
+            return Ok(ChainTransition {
+                new_state: chain_state,
+                events: vec![],
+            })
+        }
+    };
+    let transition = target::state_transition(
+        None,
+        StateChange::ActionInitTarget(state_change),
+        channel_state,
+        block_number,
+    )?;
+
+    if let Some(target_state) = transition.new_state {
+        let task = TransferTask::Target(TargetTask {
+            canonical_identifier,
+            target_state,
+        });
+        chain_state
+            .payment_mapping
+            .secrethashes_to_task
+            .insert(secrethash, task);
+    }
+
+    Ok(ChainTransition {
+        new_state: chain_state,
+        events: transition.events,
+    })
+}
+
+fn handle_payment_state_change(
+    mut chain_state: ChainState,
+    secrethash: H256,
+    state_change: StateChange,
+) -> Result<ChainTransition, StateTransitionError> {
+    let events = subdispatch_to_paymenttask(&mut chain_state, state_change, secrethash)?;
+    Ok(ChainTransition {
+        new_state: chain_state,
+        events,
     })
 }
 
@@ -85,12 +389,10 @@ fn handle_contract_receive_token_network_created(
 
 fn handle_token_network_state_change(
     mut chain_state: ChainState,
-    state_change: state_change::ContractReceiveChannelOpened,
+    token_network_address: Address,
+    state_change: StateChange,
 ) -> Result<ChainTransition, StateTransitionError> {
-    let token_network_state = views::get_token_network(
-        &chain_state,
-        &state_change.channel_state.canonical_identifier.token_network_address,
-    );
+    let token_network_state = views::get_token_network(&chain_state, &token_network_address);
     if token_network_state.is_none() {
         return Ok(ChainTransition {
             new_state: chain_state,
@@ -98,29 +400,23 @@ fn handle_token_network_state_change(
         });
     }
     let token_network_state = token_network_state.unwrap().clone();
-    let result = token_network::state_transition(
-        token_network_state,
-        StateChange::ContractReceiveChannelOpened(state_change),
-    );
+    let transition = token_network::state_transition(token_network_state, state_change)?;
 
-    if let Ok(transition) = result {
-        let new_state: TokenNetworkState = transition.new_state;
-        let registry_address =
-            views::get_token_network_registry_by_token_network_address(&chain_state, new_state.address)
-                .unwrap()
-                .address;
-        let registry = chain_state
-            .identifiers_to_tokennetworkregistries
-            .get_mut(&registry_address)
-            .unwrap();
-        registry
-            .tokennetworkaddresses_to_tokennetworks
-            .insert(new_state.address, new_state);
-    }
+    let new_state: TokenNetworkState = transition.new_state;
+    let registry_address = views::get_token_network_registry_by_token_network_address(&chain_state, new_state.address)
+        .unwrap()
+        .address;
+    let registry = chain_state
+        .identifiers_to_tokennetworkregistries
+        .get_mut(&registry_address)
+        .unwrap();
+    registry
+        .tokennetworkaddresses_to_tokennetworks
+        .insert(new_state.address, new_state);
 
     Ok(ChainTransition {
         new_state: chain_state,
-        events: vec![],
+        events: transition.events,
     })
 }
 
@@ -138,8 +434,218 @@ pub fn state_transition(
             handle_contract_receive_token_network_created(chain_state.unwrap(), state_change)
         }
         StateChange::ContractReceiveChannelOpened(state_change) => {
-            handle_token_network_state_change(chain_state.unwrap(), state_change)
+            let token_network_address = state_change.channel_state.canonical_identifier.token_network_address;
+            handle_token_network_state_change(
+                chain_state.unwrap(),
+                token_network_address,
+                StateChange::ContractReceiveChannelOpened(state_change),
+            )
+        }
+        StateChange::ContractReceiveRouteNew(state_change) => {
+            let token_network_address = state_change.canonical_identifier.token_network_address;
+            handle_token_network_state_change(
+                chain_state.unwrap(),
+                token_network_address,
+                StateChange::ContractReceiveRouteNew(state_change),
+            )
+        }
+        StateChange::ContractReceiveChannelDeposit(state_change) => {
+            let token_network_address = state_change.canonical_identifier.token_network_address;
+            handle_token_network_state_change(
+                chain_state.unwrap(),
+                token_network_address,
+                StateChange::ContractReceiveChannelDeposit(state_change),
+            )
+        }
+        StateChange::ContractReceiveChannelClosed(state_change) => {
+            let token_network_address = state_change.canonical_identifier.token_network_address;
+            handle_token_network_state_change(
+                chain_state.unwrap(),
+                token_network_address,
+                StateChange::ContractReceiveChannelClosed(state_change),
+            )
+        }
+        StateChange::ContractReceiveUpdateTransfer(state_change) => {
+            let token_network_address = state_change.canonical_identifier.token_network_address;
+            handle_token_network_state_change(
+                chain_state.unwrap(),
+                token_network_address,
+                StateChange::ContractReceiveUpdateTransfer(state_change),
+            )
+        }
+        StateChange::ContractReceiveChannelSettled(state_change) => {
+            let token_network_address = state_change.canonical_identifier.token_network_address;
+            handle_token_network_state_change(
+                chain_state.unwrap(),
+                token_network_address,
+                StateChange::ContractReceiveChannelSettled(state_change),
+            )
+        }
+        StateChange::ContractReceiveSecretReveal(state_change) => {
+            let secrethash = state_change.secrethash;
+            handle_payment_state_change(
+                chain_state.unwrap(),
+                secrethash,
+                StateChange::ContractReceiveSecretReveal(state_change),
+            )
+        }
+        StateChange::ActionInitInitiator(state_change) => handle_init_initiator(chain_state.unwrap(), state_change),
+        StateChange::ActionInitMediator(state_change) => handle_init_mediator(chain_state.unwrap(), state_change),
+        StateChange::ActionInitTarget(state_change) => handle_init_target(chain_state.unwrap(), state_change),
+        StateChange::ReceiveSecretRequest(state_change) => {
+            let secrethash = state_change.secrethash;
+            handle_payment_state_change(
+                chain_state.unwrap(),
+                secrethash,
+                StateChange::ReceiveSecretRequest(state_change),
+            )
+        }
+        StateChange::ReceiveSecretReveal(state_change) => {
+            let secrethash = state_change.secrethash;
+            handle_payment_state_change(
+                chain_state.unwrap(),
+                secrethash,
+                StateChange::ReceiveSecretReveal(state_change),
+            )
+        }
+        StateChange::ReceiveUnlock(state_change) => {
+            let secrethash = state_change.secrethash;
+            handle_payment_state_change(
+                chain_state.unwrap(),
+                secrethash,
+                StateChange::ReceiveUnlock(state_change),
+            )
+        }
+        StateChange::ReceiveLockExpired(state_change) => {
+            let secrethash = state_change.secrethash;
+            handle_payment_state_change(
+                chain_state.unwrap(),
+                secrethash,
+                StateChange::ReceiveLockExpired(state_change),
+            )
         }
     };
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::ChainID;
+    use crate::transfer::channel;
+    use crate::transfer::factories::{
+        make_block, make_default_channel_state, make_route, make_transfer_description, our_address, partner_address,
+        target_address, token_address, token_network_address, token_network_registry_address, CHANNEL_IDENTIFIER,
+    };
+    use crate::transfer::mediated_transfer::state_change::{
+        ActionInitInitiator, ReceiveSecretRequest, ReceiveSecretReveal,
+    };
+    use crate::utils::sha256_secrethash;
+    use web3::types::{U256, U64};
+
+    fn chain_state() -> ChainState {
+        let mut token_network = TokenNetworkState::new(token_network_address(), token_address());
+        token_network
+            .channelidentifiers_to_channels
+            .insert(U256::from(CHANNEL_IDENTIFIER), make_default_channel_state());
+        let registry = TokenNetworkRegistryState::new(token_network_registry_address(), vec![token_network]);
+
+        let mut chain_state = ChainState::new(ChainID::Mainnet, U64::from(1), our_address());
+        chain_state
+            .identifiers_to_tokennetworkregistries
+            .insert(registry.address, registry);
+        chain_state
+    }
+
+    fn transition(chain_state: ChainState, state_change: StateChange) -> ChainTransition {
+        state_transition(Some(chain_state), state_change).unwrap()
+    }
+
+    fn init_initiator(chain_state: ChainState, payment_identifier: u64, secret: H256) -> ChainTransition {
+        let transfer = make_transfer_description(payment_identifier, 10, our_address(), target_address(), secret);
+        let routes = vec![make_route(
+            vec![our_address(), partner_address(), target_address()],
+            CHANNEL_IDENTIFIER,
+        )];
+        transition(
+            chain_state,
+            StateChange::ActionInitInitiator(ActionInitInitiator::new(transfer, routes)),
+        )
+    }
+
+    fn block(chain_state: ChainState, block_number: U64) -> ChainTransition {
+        transition(chain_state, make_block(block_number))
+    }
+
+    #[test]
+    fn payments_transition_independently() {
+        let completed_secret = H256::from_low_u64_be(1);
+        let expired_secret = H256::from_low_u64_be(2);
+        let chain_state = init_initiator(chain_state(), 1, completed_secret).new_state;
+        let chain_state = init_initiator(chain_state, 2, expired_secret).new_state;
+        assert_eq!(chain_state.payment_mapping.secrethashes_to_task.len(), 2);
+
+        let completed_task =
+            match &chain_state.payment_mapping.secrethashes_to_task[&sha256_secrethash(&completed_secret)] {
+                TransferTask::Initiator(task) => task.manager_state.initiator.clone(),
+                task => panic!("Unexpected task {:?}", task),
+            };
+        let lock = completed_task.transfer.lock.clone();
+        let secret_request = StateChange::ReceiveSecretRequest(ReceiveSecretRequest {
+            payment_identifier: 1,
+            amount: lock.amount,
+            expiration: lock.expiration,
+            secrethash: lock.secrethash,
+            sender: target_address(),
+        });
+        let transition_result = transition(chain_state, secret_request);
+        assert!(matches!(transition_result.events[..], [Event::SendSecretReveal(_)]));
+
+        let transition_result = block(transition_result.new_state, U64::from(10));
+        assert!(transition_result.events.is_empty());
+        assert_eq!(
+            transition_result.new_state.payment_mapping.secrethashes_to_task.len(),
+            2
+        );
+
+        let secret_reveal = StateChange::ReceiveSecretReveal(ReceiveSecretReveal {
+            secret: completed_secret,
+            secrethash: lock.secrethash,
+            sender: partner_address(),
+        });
+        let transition_result = transition(transition_result.new_state, secret_reveal);
+        assert!(matches!(
+            transition_result.events[..],
+            [
+                Event::SendBalanceProof(_),
+                Event::PaymentSentSuccess(_),
+                Event::UnlockSuccess(_)
+            ]
+        ));
+        let secrethashes_to_task = &transition_result.new_state.payment_mapping.secrethashes_to_task;
+        assert!(!secrethashes_to_task.contains_key(&lock.secrethash));
+        assert!(secrethashes_to_task.contains_key(&sha256_secrethash(&expired_secret)));
+
+        let block_number = channel::get_sender_expiration_threshold(lock.expiration) + 1;
+        let transition_result = block(transition_result.new_state, block_number);
+        match &transition_result.events[..] {
+            [Event::SendLockExpired(send_lock_expired), Event::PaymentSentFailed(payment_sent_failed), Event::UnlockFailed(_)] =>
+            {
+                assert_eq!(send_lock_expired.secrethash, sha256_secrethash(&expired_secret));
+                assert_eq!(payment_sent_failed.identifier, 2);
+            }
+            events => panic!("Unexpected events {:?}", events),
+        }
+        let chain_state = transition_result.new_state;
+        assert_eq!(chain_state.block_number, block_number);
+        assert!(chain_state.payment_mapping.secrethashes_to_task.is_empty());
+        let channel_state = &views::get_token_network(&chain_state, &token_network_address())
+            .unwrap()
+            .channelidentifiers_to_channels[&U256::from(CHANNEL_IDENTIFIER)];
+        assert!(channel_state.our_state.pending_locks.locks.is_empty());
+        assert_eq!(
+            channel::get_transferred_amount(&channel_state.our_state),
+            U256::from(10)
+        );
+    }
+}
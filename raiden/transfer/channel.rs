@@ -0,0 +1,739 @@
+use crate::constants::{
+    DEFAULT_NUMBER_OF_BLOCK_CONFIRMATIONS, DEFAULT_WAIT_BEFORE_LOCK_REMOVAL, MAXIMUM_PENDING_TRANSFERS,
+};
+use crate::enums::{Event, StateChange};
+use crate::errors::{ChannelError, StateTransitionError};
+use crate::transfer::event;
+use crate::transfer::mediated_transfer::{
+    self, event::SendBalanceProof, event::SendLockExpired, event::SendLockedTransfer, state::LockedTransferSignedState,
+    state::LockedTransferUnsignedState, state_change::ReceiveLockExpired, state_change::ReceiveUnlock,
+};
+use crate::transfer::state::{
+    BalanceProofSignedState, BalanceProofUnsignedState, ChannelState, ChannelStatus, EndState, HashTimeLockState,
+    OurEndState, PendingLocksState, TransactionExecutionStatus, TransactionResult, UnlockPartialProofState,
+};
+use crate::transfer::state_change;
+use crate::utils::{keccak256, sha256_secrethash};
+use std::collections::HashMap;
+use web3::types::{Address, H256, U256, U64};
+
+pub struct ChannelTransition {
+    /// `None` once the channel is settled and has to be removed.
+    pub new_state: Option<ChannelState>,
+    pub events: Vec<Event>,
+}
+
+fn transaction_status(
+    transaction: &TransactionExecutionStatus,
+    finished: ChannelStatus,
+    running: ChannelStatus,
+) -> ChannelStatus {
+    if transaction.result == Some(TransactionResult::SUCCESS) {
+        finished
+    } else if transaction.finished_block_number.is_none() {
+        running
+    } else {
+        ChannelStatus::Unusable
+    }
+}
+
+pub fn get_status(channel_state: &ChannelState) -> ChannelStatus {
+    if let Some(settle_transaction) = &channel_state.settle_transaction {
+        transaction_status(settle_transaction, ChannelStatus::Settled, ChannelStatus::Settling)
+    } else if let Some(close_transaction) = &channel_state.close_transaction {
+        transaction_status(close_transaction, ChannelStatus::Closed, ChannelStatus::Closing)
+    } else {
+        ChannelStatus::Opened
+    }
+}
+
+/// Amount of the transfers of `end_state` which are either still pending or unlocked
+/// off-chain or on-chain but not yet part of the transferred amount.
+pub fn get_amount_locked(end_state: &impl EndState) -> U256 {
+    let locked: U256 = end_state
+        .locked_locks()
+        .values()
+        .fold(U256::zero(), |total, lock| total.saturating_add(lock.amount));
+    let unlocked: U256 = end_state
+        .unlocked_locks()
+        .values()
+        .chain(end_state.onchain_unlocked_locks().values())
+        .fold(U256::zero(), |total, unlock| total.saturating_add(unlock.lock.amount));
+
+    locked.saturating_add(unlocked)
+}
+
+pub fn get_transferred_amount(end_state: &impl EndState) -> U256 {
+    end_state.transferred_amount()
+}
+
+/// What `sender` owns in the channel: its deposit which has not been withdrawn, minus
+/// what it transferred to `receiver`, plus what `receiver` transferred to it.
+pub fn get_balance(sender: &impl EndState, receiver: &impl EndState) -> U256 {
+    sender
+        .contract_balance()
+        .saturating_sub(sender.onchain_total_withdraw())
+        .saturating_sub(sender.transferred_amount())
+        .saturating_add(receiver.transferred_amount())
+}
+
+/// The amount `sender` can still lock in new transfers to `receiver`.
+pub fn get_distributable(sender: &impl EndState, receiver: &impl EndState) -> U256 {
+    let locked_amount = get_amount_locked(sender);
+    let distributable = get_balance(sender, receiver).saturating_sub(locked_amount);
+    // A balance proof must never overflow transferred amount plus locked amount.
+    let overflow_limit = U256::max_value()
+        .saturating_sub(sender.transferred_amount())
+        .saturating_sub(locked_amount);
+
+    distributable.min(overflow_limit)
+}
+
+/// The locksroot of `pending_locks` as the token network contract computes it on unlock.
+pub fn compute_locksroot(pending_locks: &PendingLocksState) -> H256 {
+    let data: Vec<u8> = pending_locks
+        .locks
+        .iter()
+        .flat_map(|lock| lock.0.iter().cloned())
+        .collect();
+    keccak256(&data)
+}
+
+pub fn get_next_nonce(end_state: &OurEndState) -> u64 {
+    end_state.nonce + 1
+}
+
+/// Locks expire with a delay for the sender, so that the receiver has seen the
+/// expiration block confirmed before it is asked to remove the lock.
+pub fn get_sender_expiration_threshold(expiration: U64) -> U64 {
+    expiration + DEFAULT_WAIT_BEFORE_LOCK_REMOVAL
+}
+
+/// The receiver of a lock accepts its removal once the expiration block is confirmed.
+pub fn get_receiver_expiration_threshold(expiration: U64) -> U64 {
+    expiration + DEFAULT_NUMBER_OF_BLOCK_CONFIRMATIONS
+}
+
+/// A lock which was not unlocked on-chain can be removed once the block number passed the threshold.
+pub fn is_lock_expired(
+    end_state: &impl EndState,
+    lock: &HashTimeLockState,
+    block_number: U64,
+    lock_expiration_threshold: U64,
+) -> bool {
+    !end_state.onchain_unlocked_locks().contains_key(&lock.secrethash) && block_number > lock_expiration_threshold
+}
+
+/// New locks expire after twice the reveal timeout, so that each hop has its reveal timeout
+/// to learn the secret and another one to unlock on-chain.
+pub fn get_initial_lock_expiration(block_number: U64, reveal_timeout: U256) -> U64 {
+    block_number + U64::from(reveal_timeout.low_u64()) * 2
+}
+
+pub fn is_channel_usable_for_new_transfer(channel_state: &ChannelState, amount: U256) -> bool {
+    let pending_transfers = channel_state.our_state.pending_locks.locks.len();
+    let distributable = get_distributable(&channel_state.our_state, &channel_state.partner_state);
+
+    get_status(channel_state) == ChannelStatus::Opened
+        && pending_transfers < MAXIMUM_PENDING_TRANSFERS
+        && distributable >= amount
+}
+
+/// The lock of `secrethash`, whether it is pending, unlocked off-chain or unlocked on-chain.
+pub fn get_lock<'a>(end_state: &'a impl EndState, secrethash: &H256) -> Option<&'a HashTimeLockState> {
+    match end_state.locked_locks().get(secrethash) {
+        Some(lock) => Some(lock),
+        None => end_state
+            .unlocked_locks()
+            .get(secrethash)
+            .or_else(|| end_state.onchain_unlocked_locks().get(secrethash))
+            .map(|unlock| &unlock.lock),
+    }
+}
+
+fn pending_locks_without(pending_locks: &PendingLocksState, lock: &HashTimeLockState) -> PendingLocksState {
+    PendingLocksState {
+        locks: pending_locks
+            .locks
+            .iter()
+            .filter(|encoded| **encoded != lock.encoded)
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Locks a new transfer of `amount` to our partner and updates our balance proof accordingly.
+pub fn send_lockedtransfer(
+    channel_state: &mut ChannelState,
+    initiator: Address,
+    target: Address,
+    amount: U256,
+    payment_identifier: u64,
+    expiration: U64,
+    secrethash: H256,
+) -> SendLockedTransfer {
+    let our_state = &mut channel_state.our_state;
+    let lock = HashTimeLockState::new(amount, expiration, secrethash);
+
+    let mut pending_locks = our_state.pending_locks.clone();
+    pending_locks.locks.push(lock.encoded.clone());
+    let nonce = get_next_nonce(our_state);
+    let balance_proof = BalanceProofUnsignedState::new(
+        nonce,
+        our_state.transferred_amount(),
+        get_amount_locked(our_state).saturating_add(amount),
+        compute_locksroot(&pending_locks),
+        channel_state.canonical_identifier.clone(),
+    );
+
+    our_state.balance_proof = Some(balance_proof.clone());
+    our_state.nonce = nonce;
+    our_state.pending_locks = pending_locks;
+    our_state.secrethashes_to_lockedlocks.insert(secrethash, lock.clone());
+
+    let recipient = channel_state.partner_state.address;
+    SendLockedTransfer {
+        recipient,
+        message_identifier: mediated_transfer::message_identifier(secrethash, recipient, "LockedTransfer"),
+        transfer: LockedTransferUnsignedState {
+            payment_identifier,
+            token: channel_state.token_address,
+            balance_proof,
+            lock,
+            initiator,
+            target,
+        },
+    }
+}
+
+/// Unlocks our lock of `secrethash`, its amount becomes part of the transferred amount.
+/// Returns `None` if we have no such lock.
+pub fn send_unlock(
+    channel_state: &mut ChannelState,
+    payment_identifier: u64,
+    secret: H256,
+    secrethash: H256,
+) -> Option<SendBalanceProof> {
+    let our_state = &mut channel_state.our_state;
+    let lock = get_lock(our_state, &secrethash)?.clone();
+
+    let pending_locks = pending_locks_without(&our_state.pending_locks, &lock);
+    let nonce = get_next_nonce(our_state);
+    let balance_proof = BalanceProofUnsignedState::new(
+        nonce,
+        our_state.transferred_amount().saturating_add(lock.amount),
+        get_amount_locked(our_state).saturating_sub(lock.amount),
+        compute_locksroot(&pending_locks),
+        channel_state.canonical_identifier.clone(),
+    );
+
+    our_state.balance_proof = Some(balance_proof.clone());
+    our_state.nonce = nonce;
+    our_state.pending_locks = pending_locks;
+    our_state.del_lock(&secrethash);
+
+    let recipient = channel_state.partner_state.address;
+    Some(SendBalanceProof {
+        recipient,
+        message_identifier: mediated_transfer::message_identifier(secrethash, recipient, "Unlock"),
+        payment_identifier,
+        token_address: channel_state.token_address,
+        secret,
+        secrethash,
+        balance_proof,
+    })
+}
+
+/// Removes our expired lock of `secrethash`, its amount is no longer locked.
+/// Returns `None` if we have no such lock.
+pub fn send_lock_expired(channel_state: &mut ChannelState, secrethash: H256) -> Option<SendLockExpired> {
+    let our_state = &mut channel_state.our_state;
+    let lock = get_lock(our_state, &secrethash)?.clone();
+
+    let pending_locks = pending_locks_without(&our_state.pending_locks, &lock);
+    let nonce = get_next_nonce(our_state);
+    let balance_proof = BalanceProofUnsignedState::new(
+        nonce,
+        our_state.transferred_amount(),
+        get_amount_locked(our_state).saturating_sub(lock.amount),
+        compute_locksroot(&pending_locks),
+        channel_state.canonical_identifier.clone(),
+    );
+
+    our_state.balance_proof = Some(balance_proof.clone());
+    our_state.nonce = nonce;
+    our_state.pending_locks = pending_locks;
+    our_state.del_lock(&secrethash);
+
+    let recipient = channel_state.partner_state.address;
+    Some(SendLockExpired {
+        recipient,
+        message_identifier: mediated_transfer::message_identifier(secrethash, recipient, "LockExpired"),
+        balance_proof,
+        secrethash,
+    })
+}
+
+fn register_secret_endstate(
+    locked_locks: &mut HashMap<H256, HashTimeLockState>,
+    unlocked_locks: &mut HashMap<H256, UnlockPartialProofState>,
+    secret: H256,
+    secrethash: H256,
+) {
+    if let Some(lock) = locked_locks.remove(&secrethash) {
+        unlocked_locks.insert(secrethash, UnlockPartialProofState::new(lock, secret));
+    }
+}
+
+/// The secret of `secrethash` is known off-chain, the locks of both ends can be unlocked with it.
+pub fn register_offchain_secret(channel_state: &mut ChannelState, secret: H256, secrethash: H256) {
+    let our_state = &mut channel_state.our_state;
+    register_secret_endstate(
+        &mut our_state.secrethashes_to_lockedlocks,
+        &mut our_state.secrethashes_to_unlockedlocks,
+        secret,
+        secrethash,
+    );
+    let partner_state = &mut channel_state.partner_state;
+    register_secret_endstate(
+        &mut partner_state.secrethashes_to_lockedlocks,
+        &mut partner_state.secrethashes_to_unlockedlocks,
+        secret,
+        secrethash,
+    );
+}
+
+/// Moves the pending lock of `secrethash` to the on-chain unlocked locks, unless it had
+/// expired before the secret was registered.
+fn register_onchain_secret_endstate(
+    locked_locks: &mut HashMap<H256, HashTimeLockState>,
+    unlocked_locks: &mut HashMap<H256, UnlockPartialProofState>,
+    onchain_unlocked_locks: &mut HashMap<H256, UnlockPartialProofState>,
+    secret: H256,
+    secrethash: H256,
+    secret_reveal_block_number: U64,
+) {
+    let lock = match locked_locks.get(&secrethash) {
+        Some(lock) => lock.clone(),
+        None => match unlocked_locks.get(&secrethash) {
+            Some(unlock) => unlock.lock.clone(),
+            None => return,
+        },
+    };
+    if lock.expiration < secret_reveal_block_number {
+        return;
+    }
+    locked_locks.remove(&secrethash);
+    unlocked_locks.remove(&secrethash);
+    onchain_unlocked_locks.insert(secrethash, UnlockPartialProofState::new(lock, secret));
+}
+
+/// The secret of `secrethash` was registered in the SecretRegistry at `secret_reveal_block_number`.
+/// Locks which had not expired by then can be unlocked on-chain.
+pub fn register_onchain_secret(
+    channel_state: &mut ChannelState,
+    secret: H256,
+    secrethash: H256,
+    secret_reveal_block_number: U64,
+) {
+    let our_state = &mut channel_state.our_state;
+    register_onchain_secret_endstate(
+        &mut our_state.secrethashes_to_lockedlocks,
+        &mut our_state.secrethashes_to_unlockedlocks,
+        &mut our_state.secrethashes_to_onchain_unlockedlocks,
+        secret,
+        secrethash,
+        secret_reveal_block_number,
+    );
+    let partner_state = &mut channel_state.partner_state;
+    register_onchain_secret_endstate(
+        &mut partner_state.secrethashes_to_lockedlocks,
+        &mut partner_state.secrethashes_to_unlockedlocks,
+        &mut partner_state.secrethashes_to_onchain_unlockedlocks,
+        secret,
+        secrethash,
+        secret_reveal_block_number,
+    );
+}
+
+fn successful_transaction(block_number: U64) -> TransactionExecutionStatus {
+    TransactionExecutionStatus {
+        started_block_number: None,
+        finished_block_number: Some(block_number),
+        result: Some(TransactionResult::SUCCESS),
+    }
+}
+
+fn handle_channel_deposit(
+    mut channel_state: ChannelState,
+    state_change: state_change::ContractReceiveChannelDeposit,
+) -> Result<ChannelTransition, StateTransitionError> {
+    let deposit_transaction = state_change.deposit_transaction;
+    let participant_address = deposit_transaction.participant_address;
+    let total_deposit = deposit_transaction.contract_balance;
+    if participant_address == channel_state.our_state.address {
+        channel_state.our_state.contract_balance = channel_state.our_state.contract_balance.max(total_deposit);
+    } else if participant_address == channel_state.partner_state.address {
+        channel_state.partner_state.contract_balance = channel_state.partner_state.contract_balance.max(total_deposit);
+    }
+
+    Ok(ChannelTransition {
+        new_state: Some(channel_state),
+        events: vec![],
+    })
+}
+
+fn handle_channel_closed(
+    mut channel_state: ChannelState,
+    state_change: state_change::ContractReceiveChannelClosed,
+) -> Result<ChannelTransition, StateTransitionError> {
+    let close_finished = match &channel_state.close_transaction {
+        Some(close_transaction) => close_transaction.finished_block_number.is_some(),
+        None => false,
+    };
+    if !close_finished {
+        channel_state.close_transaction = Some(successful_transaction(state_change.block_number));
+    }
+
+    Ok(ChannelTransition {
+        new_state: Some(channel_state),
+        events: vec![],
+    })
+}
+
+fn handle_channel_updated_transfer(
+    mut channel_state: ChannelState,
+    state_change: state_change::ContractReceiveUpdateTransfer,
+) -> Result<ChannelTransition, StateTransitionError> {
+    channel_state.update_transaction = Some(successful_transaction(state_change.block_number));
+
+    Ok(ChannelTransition {
+        new_state: Some(channel_state),
+        events: vec![],
+    })
+}
+
+fn handle_channel_settled(
+    mut channel_state: ChannelState,
+    state_change: state_change::ContractReceiveChannelSettled,
+) -> Result<ChannelTransition, StateTransitionError> {
+    channel_state.settle_transaction = Some(successful_transaction(state_change.block_number));
+
+    let channel_settled = event::ChannelSettled {
+        canonical_identifier: channel_state.canonical_identifier,
+        block_number: state_change.block_number,
+    };
+
+    Ok(ChannelTransition {
+        new_state: None,
+        events: vec![Event::ChannelSettled(channel_settled)],
+    })
+}
+
+pub fn state_transition(
+    channel_state: ChannelState,
+    state_change: StateChange,
+) -> Result<ChannelTransition, StateTransitionError> {
+    match state_change {
+        StateChange::ContractReceiveChannelDeposit(state_change) => handle_channel_deposit(channel_state, state_change),
+        StateChange::ContractReceiveChannelClosed(state_change) => handle_channel_closed(channel_state, state_change),
+        StateChange::ContractReceiveUpdateTransfer(state_change) => {
+            handle_channel_updated_transfer(channel_state, state_change)
+        }
+        StateChange::ContractReceiveChannelSettled(state_change) => handle_channel_settled(channel_state, state_change),
+        _ => Err(StateTransitionError {
+            msg: String::from("Could not transition channel"),
+        }),
+    }
+}
+
+pub fn is_secret_known(end_state: &impl EndState, secrethash: &H256) -> bool {
+    end_state.unlocked_locks().contains_key(secrethash) || end_state.onchain_unlocked_locks().contains_key(secrethash)
+}
+
+pub fn is_secret_known_onchain(end_state: &impl EndState, secrethash: &H256) -> bool {
+    end_state.onchain_unlocked_locks().contains_key(secrethash)
+}
+
+pub fn lock_exists_in_either_channel_side(channel_state: &ChannelState, secrethash: &H256) -> bool {
+    let lock_exists = |end_state: &dyn EndState| {
+        end_state.locked_locks().contains_key(secrethash)
+            || end_state.unlocked_locks().contains_key(secrethash)
+            || end_state.onchain_unlocked_locks().contains_key(secrethash)
+    };
+    lock_exists(&channel_state.our_state) || lock_exists(&channel_state.partner_state)
+}
+
+/// Checks that a balance proof of our partner updates its end state to the given amounts
+/// and pending locks.
+fn is_valid_balance_proof_update(
+    channel_state: &ChannelState,
+    received_balance_proof: &BalanceProofSignedState,
+    transferred_amount: U256,
+    locked_amount: U256,
+    pending_locks: &PendingLocksState,
+) -> Result<(), ChannelError> {
+    let partner_state = &channel_state.partner_state;
+    let error = if received_balance_proof.canonical_identifier != channel_state.canonical_identifier {
+        "Balance proof is for another channel"
+    } else if received_balance_proof.sender != partner_state.address {
+        "Balance proof is not signed by our partner"
+    } else if received_balance_proof.nonce != partner_state.nonce + 1 {
+        "Balance proof nonce is not the next nonce"
+    } else if received_balance_proof.transferred_amount != transferred_amount {
+        "Balance proof transferred amount is wrong"
+    } else if received_balance_proof.locked_amount != locked_amount {
+        "Balance proof locked amount is wrong"
+    } else if received_balance_proof.locksroot != compute_locksroot(pending_locks) {
+        "Balance proof locksroot does not match the pending locks"
+    } else {
+        return Ok(());
+    };
+
+    Err(ChannelError { msg: error.to_string() })
+}
+
+fn update_partner_balance_proof(
+    channel_state: &mut ChannelState,
+    balance_proof: BalanceProofSignedState,
+    pending_locks: PendingLocksState,
+) {
+    let partner_state = &mut channel_state.partner_state;
+    partner_state.nonce = balance_proof.nonce;
+    partner_state.balance_proof = Some(balance_proof);
+    partner_state.pending_locks = pending_locks;
+}
+
+/// Adds the lock of a locked transfer our partner sent us.
+pub fn handle_receive_lockedtransfer(
+    channel_state: &mut ChannelState,
+    transfer: &LockedTransferSignedState,
+) -> Result<(), ChannelError> {
+    let partner_state = &channel_state.partner_state;
+    let lock = &transfer.lock;
+    let error = if get_status(channel_state) != ChannelStatus::Opened {
+        Some("Channel is not open")
+    } else if get_lock(partner_state, &lock.secrethash).is_some() {
+        Some("Lock of the secrethash is already pending")
+    } else if partner_state.pending_locks.locks.len() >= MAXIMUM_PENDING_TRANSFERS {
+        Some("Too many pending locks")
+    } else if lock.amount > get_distributable(partner_state, &channel_state.our_state) {
+        Some("Lock amount exceeds the distributable balance")
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        return Err(ChannelError { msg: error.to_string() });
+    }
+
+    let mut pending_locks = partner_state.pending_locks.clone();
+    pending_locks.locks.push(lock.encoded.clone());
+    is_valid_balance_proof_update(
+        channel_state,
+        &transfer.balance_proof,
+        partner_state.transferred_amount(),
+        get_amount_locked(partner_state).saturating_add(lock.amount),
+        &pending_locks,
+    )?;
+
+    update_partner_balance_proof(channel_state, transfer.balance_proof.clone(), pending_locks);
+    channel_state
+        .partner_state
+        .secrethashes_to_lockedlocks
+        .insert(lock.secrethash, lock.clone());
+
+    Ok(())
+}
+
+/// Moves the amount of our partner's unlocked lock into its transferred amount.
+pub fn handle_unlock(channel_state: &mut ChannelState, unlock: &ReceiveUnlock) -> Result<(), ChannelError> {
+    let partner_state = &channel_state.partner_state;
+    if sha256_secrethash(&unlock.secret) != unlock.secrethash {
+        return Err(ChannelError {
+            msg: String::from("Secret does not match the secrethash"),
+        });
+    }
+    let lock = match get_lock(partner_state, &unlock.secrethash) {
+        Some(lock) => lock,
+        None => {
+            return Err(ChannelError {
+                msg: String::from("Unknown lock"),
+            })
+        }
+    };
+
+    let pending_locks = pending_locks_without(&partner_state.pending_locks, lock);
+    is_valid_balance_proof_update(
+        channel_state,
+        &unlock.balance_proof,
+        partner_state.transferred_amount().saturating_add(lock.amount),
+        get_amount_locked(partner_state).saturating_sub(lock.amount),
+        &pending_locks,
+    )?;
+
+    update_partner_balance_proof(channel_state, unlock.balance_proof.clone(), pending_locks);
+    channel_state.partner_state.del_lock(&unlock.secrethash);
+
+    Ok(())
+}
+
+/// Removes our partner's lock which it expired.
+pub fn handle_receive_lock_expired(
+    channel_state: &mut ChannelState,
+    lock_expired: &ReceiveLockExpired,
+    block_number: U64,
+) -> Result<(), ChannelError> {
+    let partner_state = &channel_state.partner_state;
+    let lock = match get_lock(partner_state, &lock_expired.secrethash) {
+        Some(lock) => lock,
+        None => {
+            return Err(ChannelError {
+                msg: String::from("Unknown lock"),
+            })
+        }
+    };
+    let lock_expiration_threshold = get_receiver_expiration_threshold(lock.expiration);
+    if !is_lock_expired(partner_state, lock, block_number, lock_expiration_threshold) {
+        return Err(ChannelError {
+            msg: String::from("Lock has not expired yet"),
+        });
+    }
+
+    let pending_locks = pending_locks_without(&partner_state.pending_locks, lock);
+    is_valid_balance_proof_update(
+        channel_state,
+        &lock_expired.balance_proof,
+        partner_state.transferred_amount(),
+        get_amount_locked(partner_state).saturating_sub(lock.amount),
+        &pending_locks,
+    )?;
+
+    update_partner_balance_proof(channel_state, lock_expired.balance_proof.clone(), pending_locks);
+    channel_state.partner_state.del_lock(&lock_expired.secrethash);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::LOCKSROOT_OF_NO_LOCKS;
+    use crate::transfer::factories::{
+        make_default_channel_state, make_signed_balance_proof, our_address, partner_address, target_address,
+    };
+    use crate::transfer::state::hash_balance_data;
+
+    #[test]
+    fn locksroot_of_no_locks() {
+        assert_eq!(keccak256(&[]), LOCKSROOT_OF_NO_LOCKS);
+        assert_eq!(
+            compute_locksroot(&PendingLocksState { locks: vec![] }),
+            LOCKSROOT_OF_NO_LOCKS
+        );
+    }
+
+    #[test]
+    fn locksroot_hashes_the_concatenated_locks() {
+        let lock1 = HashTimeLockState::new(U256::from(10), U64::from(100), H256::from_low_u64_be(1));
+        let lock2 = HashTimeLockState::new(U256::from(20), U64::from(200), H256::from_low_u64_be(2));
+        assert_eq!(lock1.encoded.0.len(), 96);
+
+        let pending_locks = PendingLocksState {
+            locks: vec![lock1.encoded.clone(), lock2.encoded.clone()],
+        };
+        let mut data = lock1.encoded.0.clone();
+        data.extend_from_slice(&lock2.encoded.0);
+        assert_eq!(compute_locksroot(&pending_locks), keccak256(&data));
+    }
+
+    #[test]
+    fn empty_balance_hash() {
+        assert_eq!(
+            hash_balance_data(U256::zero(), U256::zero(), LOCKSROOT_OF_NO_LOCKS),
+            H256::zero()
+        );
+        assert_eq!(
+            hash_balance_data(U256::zero(), U256::zero(), H256::zero()),
+            H256::zero()
+        );
+        assert_ne!(
+            hash_balance_data(U256::from(1), U256::zero(), LOCKSROOT_OF_NO_LOCKS),
+            H256::zero()
+        );
+    }
+
+    #[test]
+    fn balance_moves_with_transferred_amounts() {
+        let mut channel_state = make_default_channel_state();
+        channel_state.our_state.onchain_total_withdraw = U256::from(10);
+        channel_state.our_state.balance_proof = Some(BalanceProofUnsignedState::new(
+            1,
+            U256::from(20),
+            U256::zero(),
+            LOCKSROOT_OF_NO_LOCKS,
+            channel_state.canonical_identifier.clone(),
+        ));
+        channel_state.partner_state.balance_proof = Some(make_signed_balance_proof(
+            &channel_state,
+            channel_state.partner_state.address,
+            1,
+            U256::from(30),
+            U256::zero(),
+            LOCKSROOT_OF_NO_LOCKS,
+        ));
+
+        let our_state = &channel_state.our_state;
+        let partner_state = &channel_state.partner_state;
+        assert_eq!(get_balance(our_state, partner_state), U256::from(100 - 10 - 20 + 30));
+        assert_eq!(get_balance(partner_state, our_state), U256::from(100 - 30 + 20));
+    }
+
+    #[test]
+    fn distributable_excludes_pending_locks() {
+        let mut channel_state = make_default_channel_state();
+        send_lockedtransfer(
+            &mut channel_state,
+            our_address(),
+            target_address(),
+            U256::from(15),
+            1,
+            U64::from(100),
+            H256::from_low_u64_be(1),
+        );
+
+        let our_state = &channel_state.our_state;
+        assert_eq!(get_amount_locked(our_state), U256::from(15));
+        assert_eq!(
+            get_distributable(our_state, &channel_state.partner_state),
+            U256::from(85)
+        );
+        assert!(is_channel_usable_for_new_transfer(&channel_state, U256::from(85)));
+        assert!(!is_channel_usable_for_new_transfer(&channel_state, U256::from(86)));
+    }
+
+    #[test]
+    fn unlock_and_lock_expired_restore_the_empty_locksroot() {
+        let mut channel_state = make_default_channel_state();
+        let secret = H256::from_low_u64_be(7);
+        let secrethash = sha256_secrethash(&secret);
+        send_lockedtransfer(
+            &mut channel_state,
+            our_address(),
+            partner_address(),
+            U256::from(15),
+            1,
+            U64::from(100),
+            secrethash,
+        );
+
+        let unlock = send_unlock(&mut channel_state, 1, secret, secrethash).unwrap();
+        assert_eq!(unlock.balance_proof.locksroot, LOCKSROOT_OF_NO_LOCKS);
+        assert_eq!(unlock.balance_proof.transferred_amount, U256::from(15));
+        assert_eq!(unlock.balance_proof.locked_amount, U256::zero());
+        assert_eq!(
+            get_distributable(&channel_state.our_state, &channel_state.partner_state),
+            U256::from(85)
+        );
+        assert!(send_lock_expired(&mut channel_state, secrethash).is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::transfer::state::TokenNetworkState;
+use crate::transfer::state::{CanonicalIdentifier, TokenNetworkState};
 use crate::transfer::state_change::ContractReceiveTokenNetworkCreated;
 use serde::{Deserialize, Serialize};
 use web3::types::{Address, H256, U64};
@@ -23,3 +23,17 @@ impl From<ContractReceiveTokenNetworkCreated> for TokenNetworkCreated {
         }
     }
 }
+
+/// One of our channels was opened, its events have to be followed from `block_number` on.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChannelOpened {
+    pub canonical_identifier: CanonicalIdentifier,
+    pub block_number: U64,
+}
+
+/// One of our channels was settled, its events no longer have to be followed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChannelSettled {
+    pub canonical_identifier: CanonicalIdentifier,
+    pub block_number: U64,
+}
@@ -0,0 +1,170 @@
+//! Builders for the states used across the state machine tests.
+
+use crate::enums::{ChainID, StateChange};
+use crate::transfer::channel;
+use crate::transfer::mediated_transfer::state::{LockedTransferSignedState, TransferDescriptionWithSecretState};
+use crate::transfer::state::{
+    hash_balance_data, BalanceProofSignedState, CanonicalIdentifier, ChannelState, HashTimeLockState,
+    PendingLocksState, RouteState, TransactionExecutionStatus, TransactionResult,
+};
+use crate::transfer::state_change::Block;
+use crate::utils::sha256_secrethash;
+use web3::types::{Address, H256, U256, U64};
+
+pub const CHAIN_ID: u64 = 1;
+pub const REVEAL_TIMEOUT: u64 = 10;
+pub const SETTLE_TIMEOUT: u64 = 500;
+pub const CHANNEL_IDENTIFIER: u64 = 1;
+
+pub fn make_address(index: u64) -> Address {
+    Address::from_low_u64_be(index)
+}
+
+pub fn our_address() -> Address {
+    make_address(1)
+}
+
+pub fn partner_address() -> Address {
+    make_address(2)
+}
+
+/// The target of payments, reached through `partner_address`.
+pub fn target_address() -> Address {
+    make_address(3)
+}
+
+/// The secret of the payments in the tests.
+pub fn secret() -> H256 {
+    H256::from_low_u64_be(42)
+}
+
+pub fn token_network_address() -> Address {
+    make_address(1000)
+}
+
+pub fn token_address() -> Address {
+    make_address(1001)
+}
+
+pub fn token_network_registry_address() -> Address {
+    make_address(1002)
+}
+
+pub fn make_canonical_identifier(channel_identifier: u64) -> CanonicalIdentifier {
+    CanonicalIdentifier {
+        chain_identifier: CHAIN_ID,
+        token_network_address: token_network_address(),
+        channel_identifier: U256::from(channel_identifier),
+    }
+}
+
+/// An open channel of the token network at `token_network_address`, funded with the given deposits.
+pub fn make_channel_state(
+    channel_identifier: u64,
+    our_address: Address,
+    partner_address: Address,
+    our_deposit: u64,
+    partner_deposit: u64,
+) -> ChannelState {
+    let open_transaction = TransactionExecutionStatus {
+        started_block_number: None,
+        finished_block_number: Some(U64::from(1)),
+        result: Some(TransactionResult::SUCCESS),
+    };
+    let mut channel_state = ChannelState::new(
+        make_canonical_identifier(channel_identifier),
+        token_address(),
+        token_network_registry_address(),
+        our_address,
+        partner_address,
+        U256::from(REVEAL_TIMEOUT),
+        U256::from(SETTLE_TIMEOUT),
+        open_transaction,
+    )
+    .unwrap();
+    channel_state.our_state.contract_balance = U256::from(our_deposit);
+    channel_state.partner_state.contract_balance = U256::from(partner_deposit);
+    channel_state
+}
+
+/// The channel `CHANNEL_IDENTIFIER` between `our_address` and `partner_address`, both sides deposited 100.
+pub fn make_default_channel_state() -> ChannelState {
+    make_channel_state(CHANNEL_IDENTIFIER, our_address(), partner_address(), 100, 100)
+}
+
+/// A new block which is confirmed right away.
+pub fn make_block(block_number: U64) -> StateChange {
+    StateChange::Block(Block::new(ChainID::Mainnet, block_number, block_number))
+}
+
+pub fn make_route(route: Vec<Address>, forward_channel_id: u64) -> RouteState {
+    RouteState {
+        route,
+        forward_channel_id: U256::from(forward_channel_id),
+    }
+}
+
+/// A payment of `amount` tokens of `token_network_address`, locked with `secret`.
+pub fn make_transfer_description(
+    payment_identifier: u64,
+    amount: u64,
+    initiator: Address,
+    target: Address,
+    secret: H256,
+) -> TransferDescriptionWithSecretState {
+    TransferDescriptionWithSecretState {
+        token_network_registry_address: token_network_registry_address(),
+        payment_identifier,
+        amount: U256::from(amount),
+        token_network_address: token_network_address(),
+        initiator,
+        target,
+        secret,
+        secrethash: sha256_secrethash(&secret),
+    }
+}
+
+pub fn make_signed_balance_proof(
+    channel_state: &ChannelState,
+    sender: Address,
+    nonce: u64,
+    transferred_amount: U256,
+    locked_amount: U256,
+    locksroot: H256,
+) -> BalanceProofSignedState {
+    BalanceProofSignedState {
+        nonce,
+        transferred_amount,
+        locked_amount,
+        locksroot,
+        message_hash: H256::zero(),
+        signature: H256::zero(),
+        sender,
+        canonical_identifier: channel_state.canonical_identifier.clone(),
+        balance_hash: hash_balance_data(transferred_amount, locked_amount, locksroot),
+    }
+}
+
+/// The first locked transfer the partner of `channel_state` sends us, locked with `secret`.
+pub fn make_received_transfer(
+    channel_state: &ChannelState,
+    amount: u64,
+    expiration: u64,
+    secret: H256,
+    target: Address,
+) -> LockedTransferSignedState {
+    let sender = channel_state.partner_state.address;
+    let lock = HashTimeLockState::new(U256::from(amount), U64::from(expiration), sha256_secrethash(&secret));
+    let locksroot = channel::compute_locksroot(&PendingLocksState {
+        locks: vec![lock.encoded.clone()],
+    });
+    LockedTransferSignedState {
+        message_identifier: 1,
+        payment_identifier: 1,
+        token: token_address(),
+        balance_proof: make_signed_balance_proof(channel_state, sender, 1, U256::zero(), lock.amount, locksroot),
+        lock,
+        initiator: sender,
+        target,
+    }
+}
@@ -0,0 +1,129 @@
+use crate::transfer::mediated_transfer::state::LockedTransferUnsignedState;
+use crate::transfer::state::BalanceProofUnsignedState;
+use serde::{Deserialize, Serialize};
+use web3::types::{Address, H256, U256, U64};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SendLockedTransfer {
+    pub recipient: Address,
+    pub message_identifier: u64,
+    pub transfer: LockedTransferUnsignedState,
+}
+
+/// Asks the initiator for the secret of a transfer we are the target of.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SendSecretRequest {
+    pub recipient: Address,
+    pub message_identifier: u64,
+    pub payment_identifier: u64,
+    pub amount: U256,
+    pub expiration: U64,
+    pub secrethash: H256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SendSecretReveal {
+    pub recipient: Address,
+    pub message_identifier: u64,
+    pub secret: H256,
+    pub secrethash: H256,
+}
+
+/// The Unlock message, it moves the amount of an unlocked lock into the transferred amount.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SendBalanceProof {
+    pub recipient: Address,
+    pub message_identifier: u64,
+    pub payment_identifier: u64,
+    pub token_address: Address,
+    pub secret: H256,
+    pub secrethash: H256,
+    pub balance_proof: BalanceProofUnsignedState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SendLockExpired {
+    pub recipient: Address,
+    pub message_identifier: u64,
+    pub balance_proof: BalanceProofUnsignedState,
+    pub secrethash: H256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PaymentSentSuccess {
+    pub token_network_registry_address: Address,
+    pub token_network_address: Address,
+    pub identifier: u64,
+    pub amount: U256,
+    pub target: Address,
+    pub secret: H256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PaymentSentFailed {
+    pub token_network_registry_address: Address,
+    pub token_network_address: Address,
+    pub identifier: u64,
+    pub target: Address,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PaymentReceivedSuccess {
+    pub token_network_registry_address: Address,
+    pub token_network_address: Address,
+    pub identifier: u64,
+    pub amount: U256,
+    pub initiator: Address,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnlockSuccess {
+    pub identifier: u64,
+    pub secrethash: H256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnlockFailed {
+    pub identifier: u64,
+    pub secrethash: H256,
+    pub reason: String,
+}
+
+/// Registers the secret on-chain, so that locks can still be unlocked on-chain after they expired off-chain.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContractSendSecretReveal {
+    pub expiration: U64,
+    pub secret: H256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnlockClaimSuccess {
+    pub identifier: u64,
+    pub secrethash: H256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnlockClaimFailed {
+    pub identifier: u64,
+    pub secrethash: H256,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InvalidReceivedLockedTransfer {
+    pub payment_identifier: u64,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InvalidReceivedUnlock {
+    pub secrethash: H256,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InvalidReceivedLockExpired {
+    pub secrethash: H256,
+    pub reason: String,
+}
@@ -0,0 +1,636 @@
+use crate::enums::{Event, StateChange};
+use crate::errors::StateTransitionError;
+use crate::transfer::channel;
+use crate::transfer::mediated_transfer::{
+    self, event,
+    state::{
+        InitiatorPaymentState, InitiatorTransferState, InitiatorTransferStatus, TransferDescriptionWithSecretState,
+    },
+    state_change::{ActionInitInitiator, ReceiveSecretRequest, ReceiveSecretReveal},
+};
+use crate::transfer::state::{ChannelState, ChannelStatus, HashTimeLockState};
+use crate::transfer::state_change::{Block, ContractReceiveSecretReveal};
+use crate::utils::sha256_secrethash;
+use std::collections::HashMap;
+use web3::types::{U256, U64};
+
+pub struct InitiatorTransition {
+    /// `None` once the payment succeeded or failed.
+    pub new_state: Option<InitiatorPaymentState>,
+    pub events: Vec<Event>,
+}
+
+fn payment_sent_failed(transfer_description: &TransferDescriptionWithSecretState, reason: &str) -> Event {
+    Event::PaymentSentFailed(event::PaymentSentFailed {
+        token_network_registry_address: transfer_description.token_network_registry_address,
+        token_network_address: transfer_description.token_network_address,
+        identifier: transfer_description.payment_identifier,
+        target: transfer_description.target,
+        reason: reason.to_string(),
+    })
+}
+
+/// The payment can no longer complete, these events finish it.
+fn unlock_failed(transfer_description: &TransferDescriptionWithSecretState, reason: &str) -> Vec<Event> {
+    vec![
+        payment_sent_failed(transfer_description, reason),
+        Event::UnlockFailed(event::UnlockFailed {
+            identifier: transfer_description.payment_identifier,
+            secrethash: transfer_description.secrethash,
+            reason: reason.to_string(),
+        }),
+    ]
+}
+
+fn handle_init(
+    state_change: ActionInitInitiator,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<InitiatorTransition, StateTransitionError> {
+    let transfer_description = state_change.transfer;
+    if sha256_secrethash(&transfer_description.secret) != transfer_description.secrethash {
+        return Ok(InitiatorTransition {
+            new_state: None,
+            events: vec![payment_sent_failed(
+                &transfer_description,
+                "Secrethash does not match the secret",
+            )],
+        });
+    }
+
+    // Each channel is handed out at most once, so that the route search can keep the channel it picked.
+    let mut channels: HashMap<U256, &mut ChannelState> = channelidentifiers_to_channels
+        .iter_mut()
+        .map(|(channel_identifier, channel_state)| (*channel_identifier, channel_state))
+        .collect();
+    let usable_route = state_change.routes.iter().find_map(|route| {
+        let channel_state = channels.remove(&route.forward_channel_id)?;
+        if channel::is_channel_usable_for_new_transfer(channel_state, transfer_description.amount) {
+            Some((route.clone(), channel_state))
+        } else {
+            None
+        }
+    });
+    let (route, channel_state) = match usable_route {
+        Some(usable_route) => usable_route,
+        None => {
+            return Ok(InitiatorTransition {
+                new_state: None,
+                events: vec![payment_sent_failed(
+                    &transfer_description,
+                    "None of the available routes could be used",
+                )],
+            })
+        }
+    };
+
+    let lock_expiration = channel::get_initial_lock_expiration(block_number, channel_state.reveal_timeout);
+    let send_locked_transfer = channel::send_lockedtransfer(
+        channel_state,
+        transfer_description.initiator,
+        transfer_description.target,
+        transfer_description.amount,
+        transfer_description.payment_identifier,
+        lock_expiration,
+        transfer_description.secrethash,
+    );
+
+    let initiator = InitiatorTransferState {
+        channel_identifier: route.forward_channel_id,
+        route,
+        transfer_description,
+        transfer: send_locked_transfer.transfer.clone(),
+        received_secret_request: false,
+        transfer_state: InitiatorTransferStatus::Pending,
+    };
+
+    Ok(InitiatorTransition {
+        new_state: Some(InitiatorPaymentState {
+            routes: state_change.routes,
+            initiator,
+        }),
+        events: vec![Event::SendLockedTransfer(send_locked_transfer)],
+    })
+}
+
+/// Our lock is expired once the sender expiration threshold passed. From then on the
+/// secret is neither revealed nor the lock unlocked.
+fn is_lock_expired(channel_state: &ChannelState, lock: &HashTimeLockState, block_number: U64) -> bool {
+    let lock_expiration_threshold = channel::get_sender_expiration_threshold(lock.expiration);
+    channel::is_lock_expired(&channel_state.our_state, lock, block_number, lock_expiration_threshold)
+}
+
+/// Expires our lock once it can no longer be unlocked, which fails the payment. A lock
+/// unlocked on-chain never expires, the payment is kept until it is unlocked.
+fn handle_block(
+    payment_state: InitiatorPaymentState,
+    state_change: Block,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+) -> Result<InitiatorTransition, StateTransitionError> {
+    let initiator = &payment_state.initiator;
+    let transfer_description = &initiator.transfer_description;
+    let secrethash = transfer_description.secrethash;
+    let channel_state = match channelidentifiers_to_channels.get_mut(&initiator.channel_identifier) {
+        Some(channel_state) => channel_state,
+        None => {
+            return Ok(InitiatorTransition {
+                new_state: None,
+                events: unlock_failed(transfer_description, "Channel no longer exists"),
+            })
+        }
+    };
+    let lock = match channel::get_lock(&channel_state.our_state, &secrethash) {
+        Some(lock) => lock,
+        None => {
+            return Ok(InitiatorTransition {
+                new_state: None,
+                events: unlock_failed(transfer_description, "Lock no longer exists"),
+            })
+        }
+    };
+
+    if !is_lock_expired(channel_state, lock, state_change.block_number) {
+        return Ok(InitiatorTransition {
+            new_state: Some(payment_state),
+            events: vec![],
+        });
+    }
+
+    let mut events = vec![];
+    if channel::get_status(channel_state) == ChannelStatus::Opened {
+        if let Some(send_lock_expired) = channel::send_lock_expired(channel_state, secrethash) {
+            events.push(Event::SendLockExpired(send_lock_expired));
+        }
+    }
+
+    let reason = if initiator.received_secret_request {
+        "Lock expired, despite receiving secret request"
+    } else {
+        "Lock expired"
+    };
+    events.extend(unlock_failed(transfer_description, reason));
+
+    Ok(InitiatorTransition {
+        new_state: None,
+        events,
+    })
+}
+
+/// Reveals the secret to the target once it asked for it with the amount and expiration we sent.
+fn handle_secretrequest(
+    mut payment_state: InitiatorPaymentState,
+    state_change: ReceiveSecretRequest,
+    channelidentifiers_to_channels: &HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<InitiatorTransition, StateTransitionError> {
+    let initiator = &mut payment_state.initiator;
+    let transfer_description = &initiator.transfer_description;
+
+    let is_message_from_target = state_change.sender == transfer_description.target
+        && state_change.secrethash == transfer_description.secrethash
+        && state_change.payment_identifier == transfer_description.payment_identifier;
+    let expired = match channelidentifiers_to_channels.get(&initiator.channel_identifier) {
+        Some(channel_state) => is_lock_expired(channel_state, &initiator.transfer.lock, block_number),
+        None => true,
+    };
+    if !is_message_from_target || initiator.received_secret_request || expired {
+        return Ok(InitiatorTransition {
+            new_state: Some(payment_state),
+            events: vec![],
+        });
+    }
+
+    let lock = &initiator.transfer.lock;
+    let is_valid_secretrequest =
+        state_change.amount == transfer_description.amount && state_change.expiration == lock.expiration;
+    initiator.received_secret_request = true;
+
+    let event = if is_valid_secretrequest {
+        initiator.transfer_state = InitiatorTransferStatus::SecretRevealed;
+        let recipient = transfer_description.target;
+        let secrethash = transfer_description.secrethash;
+        Event::SendSecretReveal(event::SendSecretReveal {
+            recipient,
+            message_identifier: mediated_transfer::message_identifier(secrethash, recipient, "RevealSecret"),
+            secret: transfer_description.secret,
+            secrethash,
+        })
+    } else {
+        Event::UnlockFailed(event::UnlockFailed {
+            identifier: transfer_description.payment_identifier,
+            secrethash: transfer_description.secrethash,
+            reason: String::from("Bad secret request message from target"),
+        })
+    };
+
+    Ok(InitiatorTransition {
+        new_state: Some(payment_state),
+        events: vec![event],
+    })
+}
+
+/// Our partner revealed the secret, which it can only know from the target. Unlocking
+/// the transfer completes the payment.
+fn handle_offchain_secretreveal(
+    payment_state: InitiatorPaymentState,
+    state_change: ReceiveSecretReveal,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<InitiatorTransition, StateTransitionError> {
+    let initiator = &payment_state.initiator;
+    let transfer_description = &initiator.transfer_description;
+    let channel_state = match channelidentifiers_to_channels.get_mut(&initiator.channel_identifier) {
+        Some(channel_state) => channel_state,
+        None => {
+            return Ok(InitiatorTransition {
+                new_state: Some(payment_state),
+                events: vec![],
+            })
+        }
+    };
+
+    let lock = &initiator.transfer.lock;
+    let valid_reveal = state_change.secret == transfer_description.secret
+        && state_change.secrethash == transfer_description.secrethash;
+    let sent_by_partner = state_change.sender == channel_state.partner_state.address;
+    let is_channel_open = channel::get_status(channel_state) == ChannelStatus::Opened;
+    let expired = is_lock_expired(channel_state, lock, block_number);
+    if !valid_reveal || !sent_by_partner || !is_channel_open || expired {
+        return Ok(InitiatorTransition {
+            new_state: Some(payment_state),
+            events: vec![],
+        });
+    }
+
+    Ok(unlock_lock(payment_state, channel_state))
+}
+
+/// The secret was registered on-chain, our lock can be unlocked unless it expired before.
+fn handle_onchain_secretreveal(
+    payment_state: InitiatorPaymentState,
+    state_change: ContractReceiveSecretReveal,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<InitiatorTransition, StateTransitionError> {
+    let initiator = &payment_state.initiator;
+    let transfer_description = &initiator.transfer_description;
+    let channel_state = match channelidentifiers_to_channels.get_mut(&initiator.channel_identifier) {
+        Some(channel_state) => channel_state,
+        None => {
+            return Ok(InitiatorTransition {
+                new_state: Some(payment_state),
+                events: vec![],
+            })
+        }
+    };
+
+    let lock = &initiator.transfer.lock;
+    let valid_reveal = state_change.secret == transfer_description.secret
+        && state_change.secrethash == transfer_description.secrethash;
+    let registered_in_time = state_change.block_number <= lock.expiration;
+    if valid_reveal && registered_in_time {
+        channel::register_onchain_secret(
+            channel_state,
+            state_change.secret,
+            state_change.secrethash,
+            state_change.block_number,
+        );
+    }
+    let is_channel_open = channel::get_status(channel_state) == ChannelStatus::Opened;
+    let expired = is_lock_expired(channel_state, lock, block_number);
+    if !valid_reveal || !registered_in_time || !is_channel_open || expired {
+        return Ok(InitiatorTransition {
+            new_state: Some(payment_state),
+            events: vec![],
+        });
+    }
+
+    Ok(unlock_lock(payment_state, channel_state))
+}
+
+/// Sends the unlock of our lock to our partner, which completes the payment.
+fn unlock_lock(payment_state: InitiatorPaymentState, channel_state: &mut ChannelState) -> InitiatorTransition {
+    let transfer_description = &payment_state.initiator.transfer_description;
+    let send_unlock = channel::send_unlock(
+        channel_state,
+        transfer_description.payment_identifier,
+        transfer_description.secret,
+        transfer_description.secrethash,
+    );
+    let send_unlock = match send_unlock {
+        Some(send_unlock) => send_unlock,
+        None => {
+            return InitiatorTransition {
+                new_state: Some(payment_state),
+                events: vec![],
+            }
+        }
+    };
+
+    let payment_sent_success = event::PaymentSentSuccess {
+        token_network_registry_address: transfer_description.token_network_registry_address,
+        token_network_address: transfer_description.token_network_address,
+        identifier: transfer_description.payment_identifier,
+        amount: transfer_description.amount,
+        target: transfer_description.target,
+        secret: transfer_description.secret,
+    };
+    let unlock_success = event::UnlockSuccess {
+        identifier: transfer_description.payment_identifier,
+        secrethash: transfer_description.secrethash,
+    };
+
+    InitiatorTransition {
+        new_state: None,
+        events: vec![
+            Event::SendBalanceProof(send_unlock),
+            Event::PaymentSentSuccess(payment_sent_success),
+            Event::UnlockSuccess(unlock_success),
+        ],
+    }
+}
+
+pub fn state_transition(
+    payment_state: Option<InitiatorPaymentState>,
+    state_change: StateChange,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<InitiatorTransition, StateTransitionError> {
+    match (payment_state, state_change) {
+        (None, StateChange::ActionInitInitiator(state_change)) => {
+            handle_init(state_change, channelidentifiers_to_channels, block_number)
+        }
+        (Some(payment_state), StateChange::Block(state_change)) => {
+            handle_block(payment_state, state_change, channelidentifiers_to_channels)
+        }
+        (Some(payment_state), StateChange::ReceiveSecretRequest(state_change)) => handle_secretrequest(
+            payment_state,
+            state_change,
+            channelidentifiers_to_channels,
+            block_number,
+        ),
+        (Some(payment_state), StateChange::ReceiveSecretReveal(state_change)) => handle_offchain_secretreveal(
+            payment_state,
+            state_change,
+            channelidentifiers_to_channels,
+            block_number,
+        ),
+        (Some(payment_state), StateChange::ContractReceiveSecretReveal(state_change)) => handle_onchain_secretreveal(
+            payment_state,
+            state_change,
+            channelidentifiers_to_channels,
+            block_number,
+        ),
+        _ => Err(StateTransitionError {
+            msg: String::from("Could not transition initiator"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::factories::{
+        make_block, make_default_channel_state, make_route, make_transfer_description, our_address, partner_address,
+        secret, target_address, CHANNEL_IDENTIFIER,
+    };
+    use crate::transfer::state::EndState;
+    use web3::types::H256;
+
+    fn channels() -> HashMap<U256, ChannelState> {
+        let mut channels = HashMap::new();
+        channels.insert(U256::from(CHANNEL_IDENTIFIER), make_default_channel_state());
+        channels
+    }
+
+    fn transfer_description(amount: u64) -> TransferDescriptionWithSecretState {
+        make_transfer_description(1, amount, our_address(), target_address(), secret())
+    }
+
+    fn init(
+        transfer_description: TransferDescriptionWithSecretState,
+        channels: &mut HashMap<U256, ChannelState>,
+    ) -> InitiatorTransition {
+        let routes = vec![make_route(
+            vec![our_address(), partner_address(), target_address()],
+            CHANNEL_IDENTIFIER,
+        )];
+        let state_change = StateChange::ActionInitInitiator(ActionInitInitiator::new(transfer_description, routes));
+        state_transition(None, state_change, channels, U64::from(1)).unwrap()
+    }
+
+    fn secret_request(payment_state: &InitiatorPaymentState) -> ReceiveSecretRequest {
+        let initiator = &payment_state.initiator;
+        ReceiveSecretRequest {
+            payment_identifier: initiator.transfer_description.payment_identifier,
+            amount: initiator.transfer_description.amount,
+            expiration: initiator.transfer.lock.expiration,
+            secrethash: initiator.transfer_description.secrethash,
+            sender: target_address(),
+        }
+    }
+
+    fn secret_reveal(payment_state: &InitiatorPaymentState) -> ReceiveSecretReveal {
+        ReceiveSecretReveal {
+            secret: payment_state.initiator.transfer_description.secret,
+            secrethash: payment_state.initiator.transfer_description.secrethash,
+            sender: partner_address(),
+        }
+    }
+
+    #[test]
+    fn init_locks_the_amount_in_the_first_usable_route() {
+        let mut channels = channels();
+        let transition = init(transfer_description(10), &mut channels);
+
+        let payment_state = transition.new_state.unwrap();
+        assert_eq!(
+            payment_state.initiator.channel_identifier,
+            U256::from(CHANNEL_IDENTIFIER)
+        );
+        assert_eq!(transition.events.len(), 1);
+        match &transition.events[0] {
+            Event::SendLockedTransfer(send_locked_transfer) => {
+                assert_eq!(send_locked_transfer.recipient, partner_address());
+                assert_eq!(send_locked_transfer.transfer.lock.amount, U256::from(10));
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        let channel_state = &channels[&U256::from(CHANNEL_IDENTIFIER)];
+        assert_eq!(channel_state.our_state.pending_locks.locks.len(), 1);
+    }
+
+    #[test]
+    fn init_fails_without_a_usable_route() {
+        let mut channels = channels();
+        let transition = init(transfer_description(1000), &mut channels);
+
+        assert!(transition.new_state.is_none());
+        assert!(matches!(transition.events[..], [Event::PaymentSentFailed(_)]));
+        let channel_state = &channels[&U256::from(CHANNEL_IDENTIFIER)];
+        assert!(channel_state.our_state.pending_locks.locks.is_empty());
+    }
+
+    #[test]
+    fn init_fails_when_the_secrethash_does_not_match() {
+        let mut channels = channels();
+        let mut transfer_description = transfer_description(10);
+        transfer_description.secrethash = H256::zero();
+        let transition = init(transfer_description, &mut channels);
+
+        assert!(transition.new_state.is_none());
+        assert!(matches!(transition.events[..], [Event::PaymentSentFailed(_)]));
+    }
+
+    #[test]
+    fn secret_request_reveals_the_secret_once() {
+        let mut channels = channels();
+        let payment_state = init(transfer_description(10), &mut channels).new_state.unwrap();
+        let state_change = StateChange::ReceiveSecretRequest(secret_request(&payment_state));
+
+        let transition =
+            state_transition(Some(payment_state), state_change.clone(), &mut channels, U64::from(2)).unwrap();
+        assert!(matches!(transition.events[..], [Event::SendSecretReveal(_)]));
+
+        let transition = state_transition(transition.new_state, state_change, &mut channels, U64::from(3)).unwrap();
+        assert!(transition.events.is_empty());
+    }
+
+    #[test]
+    fn secret_request_with_another_amount_fails_the_unlock() {
+        let mut channels = channels();
+        let payment_state = init(transfer_description(10), &mut channels).new_state.unwrap();
+        let mut secret_request = secret_request(&payment_state);
+        secret_request.amount = U256::from(9);
+
+        let state_change = StateChange::ReceiveSecretRequest(secret_request);
+        let transition = state_transition(Some(payment_state), state_change, &mut channels, U64::from(2)).unwrap();
+        assert!(matches!(transition.events[..], [Event::UnlockFailed(_)]));
+    }
+
+    #[test]
+    fn secret_reveal_unlocks_the_transfer() {
+        let mut channels = channels();
+        let payment_state = init(transfer_description(10), &mut channels).new_state.unwrap();
+        let state_change = StateChange::ReceiveSecretReveal(secret_reveal(&payment_state));
+
+        let transition = state_transition(Some(payment_state), state_change, &mut channels, U64::from(2)).unwrap();
+        assert!(transition.new_state.is_none());
+        assert!(matches!(
+            transition.events[..],
+            [
+                Event::SendBalanceProof(_),
+                Event::PaymentSentSuccess(_),
+                Event::UnlockSuccess(_)
+            ]
+        ));
+        let channel_state = &channels[&U256::from(CHANNEL_IDENTIFIER)];
+        assert!(channel_state.our_state.pending_locks.locks.is_empty());
+        assert_eq!(
+            channel_state
+                .our_state
+                .balance_proof
+                .as_ref()
+                .unwrap()
+                .transferred_amount,
+            U256::from(10)
+        );
+    }
+
+    #[test]
+    fn lock_expires_at_the_sender_expiration_threshold() {
+        let mut channels = channels();
+        let payment_state = init(transfer_description(10), &mut channels).new_state.unwrap();
+        let threshold = channel::get_sender_expiration_threshold(payment_state.initiator.transfer.lock.expiration);
+
+        let transition =
+            state_transition(Some(payment_state), make_block(threshold), &mut channels, threshold).unwrap();
+        assert!(transition.events.is_empty());
+
+        let block_number = threshold + 1;
+        let transition = state_transition(
+            transition.new_state,
+            make_block(block_number),
+            &mut channels,
+            block_number,
+        )
+        .unwrap();
+        assert!(transition.new_state.is_none());
+        assert!(matches!(
+            transition.events[..],
+            [
+                Event::SendLockExpired(_),
+                Event::PaymentSentFailed(_),
+                Event::UnlockFailed(_)
+            ]
+        ));
+        let channel_state = &channels[&U256::from(CHANNEL_IDENTIFIER)];
+        assert!(channel_state.our_state.pending_locks.locks.is_empty());
+    }
+
+    #[test]
+    fn lock_unlocked_onchain_keeps_the_payment_past_the_threshold() {
+        let mut channels = channels();
+        let payment_state = init(transfer_description(10), &mut channels).new_state.unwrap();
+        let transfer_description = &payment_state.initiator.transfer_description;
+        let channel_state = channels.get_mut(&U256::from(CHANNEL_IDENTIFIER)).unwrap();
+        channel::register_onchain_secret(
+            channel_state,
+            transfer_description.secret,
+            transfer_description.secrethash,
+            U64::from(2),
+        );
+
+        let block_number =
+            channel::get_sender_expiration_threshold(payment_state.initiator.transfer.lock.expiration) + 1;
+        let transition = state_transition(
+            Some(payment_state),
+            make_block(block_number),
+            &mut channels,
+            block_number,
+        )
+        .unwrap();
+        assert!(transition.new_state.is_some());
+        assert!(transition.events.is_empty());
+    }
+
+    #[test]
+    fn payment_fails_when_its_lock_is_gone() {
+        let mut channels = channels();
+        let payment_state = init(transfer_description(10), &mut channels).new_state.unwrap();
+        let secrethash = payment_state.initiator.transfer_description.secrethash;
+        let channel_state = channels.get_mut(&U256::from(CHANNEL_IDENTIFIER)).unwrap();
+        channel_state.our_state.del_lock(&secrethash);
+
+        let transition = state_transition(
+            Some(payment_state),
+            make_block(U64::from(2)),
+            &mut channels,
+            U64::from(2),
+        )
+        .unwrap();
+        assert!(transition.new_state.is_none());
+        assert!(matches!(
+            transition.events[..],
+            [Event::PaymentSentFailed(_), Event::UnlockFailed(_)]
+        ));
+    }
+
+    #[test]
+    fn secret_is_neither_revealed_nor_unlocked_past_the_threshold() {
+        let mut channels = channels();
+        let payment_state = init(transfer_description(10), &mut channels).new_state.unwrap();
+        let block_number =
+            channel::get_sender_expiration_threshold(payment_state.initiator.transfer.lock.expiration) + 1;
+
+        let state_change = StateChange::ReceiveSecretRequest(secret_request(&payment_state));
+        let transition = state_transition(Some(payment_state), state_change, &mut channels, block_number).unwrap();
+        assert!(transition.events.is_empty());
+
+        let payment_state = transition.new_state.unwrap();
+        let state_change = StateChange::ReceiveSecretReveal(secret_reveal(&payment_state));
+        let transition = state_transition(Some(payment_state), state_change, &mut channels, block_number).unwrap();
+        assert!(transition.new_state.is_some());
+        assert!(transition.events.is_empty());
+    }
+}
@@ -0,0 +1,882 @@
+use crate::enums::{Event, StateChange};
+use crate::errors::StateTransitionError;
+use crate::transfer::channel;
+use crate::transfer::mediated_transfer::{
+    self, event,
+    state::{LockedTransferSignedState, MediationPairState, MediatorTransferState, PayeeStatus, PayerStatus},
+    state_change::{ActionInitMediator, ReceiveLockExpired, ReceiveSecretReveal, ReceiveUnlock},
+};
+use crate::transfer::state::{ChannelState, ChannelStatus};
+use crate::transfer::state_change::{Block, ContractReceiveSecretReveal};
+use crate::utils::sha256_secrethash;
+use std::collections::HashMap;
+use web3::types::{Address, H256, U256, U64};
+
+pub struct MediatorTransition {
+    /// `None` once the locks of all transfers of the payment are gone from our channels.
+    pub new_state: Option<MediatorTransferState>,
+    pub events: Vec<Event>,
+}
+
+/// Waiting for the secret is safe as long as there are more blocks left until the lock
+/// expires than it takes to register the secret on-chain.
+pub fn is_safe_to_wait(lock_expiration: U64, reveal_timeout: U256, block_number: U64) -> bool {
+    if block_number >= lock_expiration {
+        return false;
+    }
+    let lock_timeout = U256::from((lock_expiration - block_number).as_u64());
+    lock_timeout > reveal_timeout
+}
+
+/// A payee channel has to leave its partner the reveal timeout to learn the secret before
+/// the lock expires, and must not settle before it does.
+fn is_channel_usable_for_mediation(channel_state: &ChannelState, amount: U256, lock_timeout: U64) -> bool {
+    let lock_timeout = U256::from(lock_timeout.as_u64());
+    channel::is_channel_usable_for_new_transfer(channel_state, amount)
+        && channel_state.reveal_timeout < lock_timeout
+        && lock_timeout < channel_state.settle_timeout
+}
+
+fn payer_channel_identifier(payer_transfer: &LockedTransferSignedState) -> U256 {
+    payer_transfer.balance_proof.canonical_identifier.channel_identifier
+}
+
+fn payee_channel_identifier(pair: &MediationPairState) -> U256 {
+    pair.payee_transfer
+        .balance_proof
+        .canonical_identifier
+        .channel_identifier
+}
+
+/// Forwards the payer transfer over the first route which is usable for its lock. The
+/// forwarded lock expires along with the payer lock.
+fn forward_transfer_pair(
+    mediator_state: &mut MediatorTransferState,
+    payer_transfer: LockedTransferSignedState,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Vec<Event> {
+    let lock = payer_transfer.lock.clone();
+    let payer_channel_identifier = payer_channel_identifier(&payer_transfer);
+    let payer_channel = match channelidentifiers_to_channels.get(&payer_channel_identifier) {
+        Some(payer_channel) => payer_channel,
+        None => return vec![],
+    };
+    let payer_address = payer_channel.partner_state.address;
+
+    let payee_channel_identifier = if is_safe_to_wait(lock.expiration, payer_channel.reveal_timeout, block_number) {
+        let lock_timeout = lock.expiration - block_number;
+        mediator_state
+            .routes
+            .iter()
+            .map(|route| route.forward_channel_id)
+            .find(
+                |channel_identifier| match channelidentifiers_to_channels.get(channel_identifier) {
+                    Some(channel_state) => {
+                        channel_state.partner_state.address != payer_address
+                            && is_channel_usable_for_mediation(channel_state, lock.amount, lock_timeout)
+                    }
+                    None => false,
+                },
+            )
+    } else {
+        None
+    };
+    let payee_channel = match payee_channel_identifier {
+        Some(channel_identifier) => channelidentifiers_to_channels.get_mut(&channel_identifier).unwrap(),
+        None => {
+            mediator_state.waiting_transfer = Some(payer_transfer);
+            return vec![];
+        }
+    };
+
+    let send_locked_transfer = channel::send_lockedtransfer(
+        payee_channel,
+        payer_transfer.initiator,
+        payer_transfer.target,
+        lock.amount,
+        payer_transfer.payment_identifier,
+        lock.expiration,
+        lock.secrethash,
+    );
+    mediator_state.transfers_pair.push(MediationPairState {
+        payer_transfer,
+        payee_address: payee_channel.partner_state.address,
+        payee_transfer: send_locked_transfer.transfer.clone(),
+        payer_state: PayerStatus::Pending,
+        payee_state: PayeeStatus::Pending,
+    });
+
+    vec![Event::SendLockedTransfer(send_locked_transfer)]
+}
+
+/// Sends the unlock to payees which proved they know the secret.
+fn events_for_balanceproof(
+    mediator_state: &mut MediatorTransferState,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Vec<Event> {
+    let secret = match mediator_state.secret {
+        Some(secret) => secret,
+        None => return vec![],
+    };
+    let mut events = vec![];
+    for pair in mediator_state.transfers_pair.iter_mut() {
+        if pair.payee_state != PayeeStatus::SecretRevealed {
+            continue;
+        }
+        let payee_channel = match channelidentifiers_to_channels.get_mut(&payee_channel_identifier(pair)) {
+            Some(payee_channel) => payee_channel,
+            None => continue,
+        };
+        let lock = &pair.payee_transfer.lock;
+        let lock_valid = !channel::is_lock_expired(&payee_channel.our_state, lock, block_number, lock.expiration);
+        if channel::get_status(payee_channel) != ChannelStatus::Opened || !lock_valid {
+            continue;
+        }
+
+        let send_unlock = channel::send_unlock(
+            payee_channel,
+            pair.payee_transfer.payment_identifier,
+            secret,
+            mediator_state.secrethash,
+        );
+        if let Some(send_unlock) = send_unlock {
+            pair.payee_state = PayeeStatus::BalanceProof;
+            events.push(Event::SendBalanceProof(send_unlock));
+        }
+    }
+    events
+}
+
+/// Reveals the secret backwards to payers whose payee learned it.
+fn events_for_secretreveal(mediator_state: &mut MediatorTransferState) -> Vec<Event> {
+    let secret = match mediator_state.secret {
+        Some(secret) => secret,
+        None => return vec![],
+    };
+    let secrethash = mediator_state.secrethash;
+    let mut events = vec![];
+    for pair in mediator_state.transfers_pair.iter_mut().rev() {
+        let payee_knows_secret = match pair.payee_state {
+            PayeeStatus::SecretRevealed | PayeeStatus::BalanceProof => true,
+            PayeeStatus::Pending | PayeeStatus::Expired => false,
+        };
+        if pair.payer_state != PayerStatus::Pending || !payee_knows_secret {
+            continue;
+        }
+
+        pair.payer_state = PayerStatus::SecretRevealed;
+        let recipient = pair.payer_transfer.balance_proof.sender;
+        events.push(Event::SendSecretReveal(event::SendSecretReveal {
+            recipient,
+            message_identifier: mediated_transfer::message_identifier(secrethash, recipient, "RevealSecret"),
+            secret,
+            secrethash,
+        }));
+    }
+    events
+}
+
+/// Remembers the secret revealed by `payee_address`, or registered on-chain when there is
+/// none, unlocks the payee transfers and asks the payers for their unlock by revealing the
+/// secret to them.
+fn secret_learned(
+    mediator_state: &mut MediatorTransferState,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+    secret: H256,
+    payee_address: Option<Address>,
+) -> Vec<Event> {
+    let secrethash = mediator_state.secrethash;
+    mediator_state.secret = Some(secret);
+    for pair in mediator_state.transfers_pair.iter_mut() {
+        for channel_identifier in &[
+            payer_channel_identifier(&pair.payer_transfer),
+            payee_channel_identifier(pair),
+        ] {
+            if let Some(channel_state) = channelidentifiers_to_channels.get_mut(channel_identifier) {
+                channel::register_offchain_secret(channel_state, secret, secrethash);
+            }
+        }
+        if Some(pair.payee_address) == payee_address && pair.payee_state == PayeeStatus::Pending {
+            pair.payee_state = PayeeStatus::SecretRevealed;
+        }
+    }
+
+    let mut events = events_for_balanceproof(mediator_state, channelidentifiers_to_channels, block_number);
+    events.extend(events_for_secretreveal(mediator_state));
+    events
+}
+
+/// Expires payee locks which can no longer be unlocked and flags expired payer locks,
+/// including the one of the waiting transfer, whose removal the payer has to send.
+fn events_for_expired_pairs(
+    mediator_state: &mut MediatorTransferState,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Vec<Event> {
+    let secrethash = mediator_state.secrethash;
+    let mut events = vec![];
+    for pair in mediator_state.transfers_pair.iter_mut().rev() {
+        let payee_final = match pair.payee_state {
+            PayeeStatus::BalanceProof | PayeeStatus::Expired => true,
+            PayeeStatus::Pending | PayeeStatus::SecretRevealed => false,
+        };
+        if let Some(payee_channel) = channelidentifiers_to_channels.get_mut(&payee_channel_identifier(pair)) {
+            let lock = &pair.payee_transfer.lock;
+            let lock_expiration_threshold = channel::get_sender_expiration_threshold(lock.expiration);
+            let has_lock_expired =
+                channel::is_lock_expired(&payee_channel.our_state, lock, block_number, lock_expiration_threshold);
+            let is_channel_open = channel::get_status(payee_channel) == ChannelStatus::Opened;
+            if !payee_final && has_lock_expired && is_channel_open {
+                if let Some(send_lock_expired) = channel::send_lock_expired(payee_channel, secrethash) {
+                    events.push(Event::SendLockExpired(send_lock_expired));
+                }
+                pair.payee_state = PayeeStatus::Expired;
+                events.push(Event::UnlockFailed(event::UnlockFailed {
+                    identifier: pair.payee_transfer.payment_identifier,
+                    secrethash,
+                    reason: String::from("Lock expired"),
+                }));
+            }
+        }
+
+        let payer_final = match pair.payer_state {
+            PayerStatus::BalanceProof | PayerStatus::Expired => true,
+            PayerStatus::Pending | PayerStatus::SecretRevealed | PayerStatus::WaitingSecretReveal => false,
+        };
+        if let Some(payer_channel) = channelidentifiers_to_channels.get(&payer_channel_identifier(&pair.payer_transfer))
+        {
+            let lock = &pair.payer_transfer.lock;
+            let lock_expiration_threshold = channel::get_receiver_expiration_threshold(lock.expiration);
+            let has_lock_expired = channel::is_lock_expired(
+                &payer_channel.partner_state,
+                lock,
+                block_number,
+                lock_expiration_threshold,
+            );
+            if !payer_final && has_lock_expired {
+                pair.payer_state = PayerStatus::Expired;
+                events.push(Event::UnlockClaimFailed(event::UnlockClaimFailed {
+                    identifier: pair.payer_transfer.payment_identifier,
+                    secrethash,
+                    reason: String::from("Lock expired"),
+                }));
+            }
+        }
+    }
+
+    if let Some(waiting_transfer) = &mediator_state.waiting_transfer {
+        if let Some(payer_channel) = channelidentifiers_to_channels.get(&payer_channel_identifier(waiting_transfer)) {
+            let lock = &waiting_transfer.lock;
+            let lock_expiration_threshold = channel::get_receiver_expiration_threshold(lock.expiration);
+            let has_lock_expired = channel::is_lock_expired(
+                &payer_channel.partner_state,
+                lock,
+                block_number,
+                lock_expiration_threshold,
+            );
+            if !mediator_state.waiting_transfer_expired && has_lock_expired {
+                mediator_state.waiting_transfer_expired = true;
+                events.push(Event::UnlockClaimFailed(event::UnlockClaimFailed {
+                    identifier: waiting_transfer.payment_identifier,
+                    secrethash,
+                    reason: String::from("Lock expired"),
+                }));
+            }
+        }
+    }
+    events
+}
+
+/// Registers the secret on-chain once a payer lock we know the secret of gets too close to
+/// its expiration to wait for the payer's unlock.
+fn events_for_onchain_secretreveal_if_dangerzone(
+    mediator_state: &mut MediatorTransferState,
+    channelidentifiers_to_channels: &HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Vec<Event> {
+    let secret = match mediator_state.secret {
+        Some(secret) => secret,
+        None => return vec![],
+    };
+    let mut events = vec![];
+    for pair in mediator_state.transfers_pair.iter_mut() {
+        if pair.payer_state != PayerStatus::Pending && pair.payer_state != PayerStatus::SecretRevealed {
+            continue;
+        }
+        let payer_channel = match channelidentifiers_to_channels.get(&payer_channel_identifier(&pair.payer_transfer)) {
+            Some(payer_channel) => payer_channel,
+            None => continue,
+        };
+        let lock = &pair.payer_transfer.lock;
+        let safe_to_wait = is_safe_to_wait(lock.expiration, payer_channel.reveal_timeout, block_number);
+        let registered = channel::is_secret_known_onchain(&payer_channel.partner_state, &lock.secrethash);
+        if safe_to_wait || registered || block_number >= lock.expiration {
+            continue;
+        }
+
+        pair.payer_state = PayerStatus::WaitingSecretReveal;
+        if events.is_empty() {
+            events.push(Event::ContractSendSecretReveal(event::ContractSendSecretReveal {
+                expiration: lock.expiration,
+                secret,
+            }));
+        }
+    }
+    events
+}
+
+/// Retries forwarding the transfer none of the routes was usable for. It stays parked once
+/// waiting for the secret is no longer safe, see `forward_transfer_pair`.
+fn events_for_waiting_transfer(
+    mediator_state: &mut MediatorTransferState,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Vec<Event> {
+    let waiting_transfer = match mediator_state.waiting_transfer.take() {
+        Some(waiting_transfer) => waiting_transfer,
+        None => return vec![],
+    };
+    let is_payer_lock_pending = match channelidentifiers_to_channels.get(&payer_channel_identifier(&waiting_transfer)) {
+        Some(payer_channel) => payer_channel
+            .partner_state
+            .secrethashes_to_lockedlocks
+            .contains_key(&waiting_transfer.lock.secrethash),
+        None => false,
+    };
+    if mediator_state.secret.is_some() || !is_payer_lock_pending {
+        mediator_state.waiting_transfer = Some(waiting_transfer);
+        return vec![];
+    }
+
+    forward_transfer_pair(
+        mediator_state,
+        waiting_transfer,
+        channelidentifiers_to_channels,
+        block_number,
+    )
+}
+
+/// The task is done once the locks of all its transfers are gone from both sides of our channels.
+fn clear_if_finalized(
+    mediator_state: MediatorTransferState,
+    channelidentifiers_to_channels: &HashMap<U256, ChannelState>,
+    events: Vec<Event>,
+) -> MediatorTransition {
+    let secrethash = mediator_state.secrethash;
+    let lock_exists = |channel_identifier: U256| match channelidentifiers_to_channels.get(&channel_identifier) {
+        Some(channel_state) => channel::lock_exists_in_either_channel_side(channel_state, &secrethash),
+        None => false,
+    };
+
+    let pairs_pending = mediator_state.transfers_pair.iter().any(|pair| {
+        lock_exists(payer_channel_identifier(&pair.payer_transfer)) || lock_exists(payee_channel_identifier(pair))
+    });
+    let waiting_pending = match &mediator_state.waiting_transfer {
+        Some(waiting_transfer) => lock_exists(payer_channel_identifier(waiting_transfer)),
+        None => false,
+    };
+
+    MediatorTransition {
+        new_state: if pairs_pending || waiting_pending {
+            Some(mediator_state)
+        } else {
+            None
+        },
+        events,
+    }
+}
+
+fn handle_init(
+    state_change: ActionInitMediator,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<MediatorTransition, StateTransitionError> {
+    let from_transfer = state_change.from_transfer;
+    let payer_channel = match channelidentifiers_to_channels.get_mut(&payer_channel_identifier(&from_transfer)) {
+        Some(payer_channel) => payer_channel,
+        None => {
+            return Ok(MediatorTransition {
+                new_state: None,
+                events: vec![],
+            })
+        }
+    };
+
+    if let Err(e) = channel::handle_receive_lockedtransfer(payer_channel, &from_transfer) {
+        let invalid_locked_transfer = event::InvalidReceivedLockedTransfer {
+            payment_identifier: from_transfer.payment_identifier,
+            reason: e.msg,
+        };
+        return Ok(MediatorTransition {
+            new_state: None,
+            events: vec![Event::InvalidReceivedLockedTransfer(invalid_locked_transfer)],
+        });
+    }
+
+    let mut mediator_state = MediatorTransferState::new(from_transfer.lock.secrethash, state_change.routes);
+    let events = forward_transfer_pair(
+        &mut mediator_state,
+        from_transfer,
+        channelidentifiers_to_channels,
+        block_number,
+    );
+
+    Ok(MediatorTransition {
+        new_state: Some(mediator_state),
+        events,
+    })
+}
+
+fn handle_block(
+    mut mediator_state: MediatorTransferState,
+    state_change: Block,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+) -> Result<MediatorTransition, StateTransitionError> {
+    let block_number = state_change.block_number;
+    let mut events = events_for_expired_pairs(&mut mediator_state, channelidentifiers_to_channels, block_number);
+    events.extend(events_for_onchain_secretreveal_if_dangerzone(
+        &mut mediator_state,
+        channelidentifiers_to_channels,
+        block_number,
+    ));
+    events.extend(events_for_waiting_transfer(
+        &mut mediator_state,
+        channelidentifiers_to_channels,
+        block_number,
+    ));
+
+    Ok(clear_if_finalized(
+        mediator_state,
+        channelidentifiers_to_channels,
+        events,
+    ))
+}
+
+fn handle_offchain_secretreveal(
+    mut mediator_state: MediatorTransferState,
+    state_change: ReceiveSecretReveal,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<MediatorTransition, StateTransitionError> {
+    let is_valid_reveal = state_change.secrethash == mediator_state.secrethash
+        && sha256_secrethash(&state_change.secret) == mediator_state.secrethash;
+    let is_secret_unknown = mediator_state.secret.is_none();
+    let sent_by_payee = mediator_state
+        .transfers_pair
+        .iter()
+        .any(|pair| pair.payee_address == state_change.sender);
+    // A secret revealed after the payer lock expired cannot be claimed off-chain anymore.
+    let has_payer_transfer_expired = match mediator_state.transfers_pair.last() {
+        Some(pair) => match channelidentifiers_to_channels.get(&payer_channel_identifier(&pair.payer_transfer)) {
+            Some(payer_channel) => {
+                let lock = &pair.payer_transfer.lock;
+                channel::is_lock_expired(&payer_channel.partner_state, lock, block_number, lock.expiration)
+            }
+            None => true,
+        },
+        None => true,
+    };
+
+    let events = if is_valid_reveal && is_secret_unknown && sent_by_payee && !has_payer_transfer_expired {
+        secret_learned(
+            &mut mediator_state,
+            channelidentifiers_to_channels,
+            block_number,
+            state_change.secret,
+            Some(state_change.sender),
+        )
+    } else {
+        vec![]
+    };
+
+    Ok(MediatorTransition {
+        new_state: Some(mediator_state),
+        events,
+    })
+}
+
+/// The secret was registered on-chain, either by us once a payer lock got too close to its
+/// expiration or by another participant of the payment. Locks which had not expired by the
+/// block of the registration can be unlocked with it.
+fn handle_onchain_secretreveal(
+    mut mediator_state: MediatorTransferState,
+    state_change: ContractReceiveSecretReveal,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<MediatorTransition, StateTransitionError> {
+    let secrethash = mediator_state.secrethash;
+    let is_valid_reveal =
+        state_change.secrethash == secrethash && sha256_secrethash(&state_change.secret) == secrethash;
+    if !is_valid_reveal {
+        return Ok(MediatorTransition {
+            new_state: Some(mediator_state),
+            events: vec![],
+        });
+    }
+
+    let channel_identifiers: Vec<U256> = mediator_state
+        .transfers_pair
+        .iter()
+        .flat_map(|pair| {
+            vec![
+                payer_channel_identifier(&pair.payer_transfer),
+                payee_channel_identifier(pair),
+            ]
+        })
+        .chain(mediator_state.waiting_transfer.iter().map(payer_channel_identifier))
+        .collect();
+    for channel_identifier in channel_identifiers {
+        if let Some(channel_state) = channelidentifiers_to_channels.get_mut(&channel_identifier) {
+            channel::register_onchain_secret(
+                channel_state,
+                state_change.secret,
+                secrethash,
+                state_change.block_number,
+            );
+        }
+    }
+    for pair in mediator_state.transfers_pair.iter_mut() {
+        if pair.payer_state == PayerStatus::WaitingSecretReveal {
+            pair.payer_state = PayerStatus::SecretRevealed;
+        }
+    }
+
+    let events = secret_learned(
+        &mut mediator_state,
+        channelidentifiers_to_channels,
+        block_number,
+        state_change.secret,
+        None,
+    );
+    Ok(clear_if_finalized(
+        mediator_state,
+        channelidentifiers_to_channels,
+        events,
+    ))
+}
+
+fn handle_unlock(
+    mut mediator_state: MediatorTransferState,
+    state_change: ReceiveUnlock,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+) -> Result<MediatorTransition, StateTransitionError> {
+    let mut events = vec![];
+    for pair in mediator_state.transfers_pair.iter_mut() {
+        let payer_balance_proof = &pair.payer_transfer.balance_proof;
+        if payer_balance_proof.sender != state_change.balance_proof.sender {
+            continue;
+        }
+        let payer_channel =
+            match channelidentifiers_to_channels.get_mut(&payer_channel_identifier(&pair.payer_transfer)) {
+                Some(payer_channel) => payer_channel,
+                None => continue,
+            };
+
+        match channel::handle_unlock(payer_channel, &state_change) {
+            Ok(()) => {
+                pair.payer_state = PayerStatus::BalanceProof;
+                events.push(Event::UnlockClaimSuccess(event::UnlockClaimSuccess {
+                    identifier: pair.payer_transfer.payment_identifier,
+                    secrethash: mediator_state.secrethash,
+                }));
+            }
+            Err(e) => events.push(Event::InvalidReceivedUnlock(event::InvalidReceivedUnlock {
+                secrethash: mediator_state.secrethash,
+                reason: e.msg,
+            })),
+        }
+    }
+
+    Ok(clear_if_finalized(
+        mediator_state,
+        channelidentifiers_to_channels,
+        events,
+    ))
+}
+
+fn handle_lock_expired(
+    mut mediator_state: MediatorTransferState,
+    state_change: ReceiveLockExpired,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<MediatorTransition, StateTransitionError> {
+    let secrethash = mediator_state.secrethash;
+    let channel_identifier = state_change.balance_proof.canonical_identifier.channel_identifier;
+    let is_payer_transfer = mediator_state
+        .transfers_pair
+        .iter()
+        .map(|pair| &pair.payer_transfer)
+        .chain(mediator_state.waiting_transfer.iter())
+        .any(|payer_transfer| payer_channel_identifier(payer_transfer) == channel_identifier);
+    let payer_channel = match channelidentifiers_to_channels.get_mut(&channel_identifier) {
+        Some(payer_channel) if is_payer_transfer => payer_channel,
+        _ => {
+            return Ok(MediatorTransition {
+                new_state: Some(mediator_state),
+                events: vec![],
+            })
+        }
+    };
+
+    let mut events = vec![];
+    match channel::handle_receive_lock_expired(payer_channel, &state_change, block_number) {
+        Ok(()) => {
+            for pair in mediator_state.transfers_pair.iter_mut() {
+                if payer_channel_identifier(&pair.payer_transfer) == channel_identifier {
+                    pair.payer_state = PayerStatus::Expired;
+                }
+            }
+        }
+        Err(e) => events.push(Event::InvalidReceivedLockExpired(event::InvalidReceivedLockExpired {
+            secrethash,
+            reason: e.msg,
+        })),
+    }
+
+    Ok(clear_if_finalized(
+        mediator_state,
+        channelidentifiers_to_channels,
+        events,
+    ))
+}
+
+pub fn state_transition(
+    mediator_state: Option<MediatorTransferState>,
+    state_change: StateChange,
+    channelidentifiers_to_channels: &mut HashMap<U256, ChannelState>,
+    block_number: U64,
+) -> Result<MediatorTransition, StateTransitionError> {
+    match (mediator_state, state_change) {
+        (None, StateChange::ActionInitMediator(state_change)) => {
+            handle_init(state_change, channelidentifiers_to_channels, block_number)
+        }
+        (Some(mediator_state), StateChange::Block(state_change)) => {
+            handle_block(mediator_state, state_change, channelidentifiers_to_channels)
+        }
+        (Some(mediator_state), StateChange::ReceiveSecretReveal(state_change)) => handle_offchain_secretreveal(
+            mediator_state,
+            state_change,
+            channelidentifiers_to_channels,
+            block_number,
+        ),
+        (Some(mediator_state), StateChange::ContractReceiveSecretReveal(state_change)) => handle_onchain_secretreveal(
+            mediator_state,
+            state_change,
+            channelidentifiers_to_channels,
+            block_number,
+        ),
+        (Some(mediator_state), StateChange::ReceiveUnlock(state_change)) => {
+            handle_unlock(mediator_state, state_change, channelidentifiers_to_channels)
+        }
+        (Some(mediator_state), StateChange::ReceiveLockExpired(state_change)) => handle_lock_expired(
+            mediator_state,
+            state_change,
+            channelidentifiers_to_channels,
+            block_number,
+        ),
+        _ => Err(StateTransitionError {
+            msg: String::from("Could not transition mediator"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::factories::{
+        make_address, make_block, make_channel_state, make_default_channel_state, make_received_transfer, make_route,
+        our_address, secret, target_address, CHANNEL_IDENTIFIER,
+    };
+
+    const PAYER_CHANNEL: u64 = CHANNEL_IDENTIFIER;
+    const PAYEE_CHANNEL: u64 = 2;
+    const LOCK_EXPIRATION: u64 = 50;
+
+    fn channels() -> HashMap<U256, ChannelState> {
+        let mut channels = HashMap::new();
+        let payee_channel = make_channel_state(PAYEE_CHANNEL, our_address(), target_address(), 100, 0);
+        channels.insert(U256::from(PAYER_CHANNEL), make_default_channel_state());
+        channels.insert(U256::from(PAYEE_CHANNEL), payee_channel);
+        channels
+    }
+
+    fn init(channels: &mut HashMap<U256, ChannelState>) -> MediatorTransition {
+        let payer_channel = &channels[&U256::from(PAYER_CHANNEL)];
+        let from_transfer = make_received_transfer(payer_channel, 10, LOCK_EXPIRATION, secret(), target_address());
+        let routes = vec![make_route(vec![our_address(), target_address()], PAYEE_CHANNEL)];
+        let state_change = StateChange::ActionInitMediator(ActionInitMediator { routes, from_transfer });
+        state_transition(None, state_change, channels, U64::from(1)).unwrap()
+    }
+
+    fn block(
+        mediator_state: MediatorTransferState,
+        channels: &mut HashMap<U256, ChannelState>,
+        block_number: u64,
+    ) -> MediatorTransition {
+        let block_number = U64::from(block_number);
+        state_transition(Some(mediator_state), make_block(block_number), channels, block_number).unwrap()
+    }
+
+    fn onchain_secret_reveal(
+        mediator_state: MediatorTransferState,
+        channels: &mut HashMap<U256, ChannelState>,
+        block_number: u64,
+    ) -> MediatorTransition {
+        let state_change = StateChange::ContractReceiveSecretReveal(ContractReceiveSecretReveal::new(
+            H256::zero(),
+            make_address(2000),
+            sha256_secrethash(&secret()),
+            secret(),
+            U64::from(block_number),
+            H256::zero(),
+        ));
+        state_transition(Some(mediator_state), state_change, channels, U64::from(block_number)).unwrap()
+    }
+
+    #[test]
+    fn onchain_secret_reveal_ends_the_wait_for_the_registration() {
+        let mut channels = channels();
+        let mediator_state = init(&mut channels).new_state.unwrap();
+
+        let state_change = StateChange::ReceiveSecretReveal(ReceiveSecretReveal {
+            secret: secret(),
+            secrethash: sha256_secrethash(&secret()),
+            sender: target_address(),
+        });
+        let transition = state_transition(Some(mediator_state), state_change, &mut channels, U64::from(2)).unwrap();
+        assert!(matches!(
+            transition.events[..],
+            [Event::SendBalanceProof(_), Event::SendSecretReveal(_)]
+        ));
+
+        let transition = block(transition.new_state.unwrap(), &mut channels, 45);
+        assert!(matches!(transition.events[..], [Event::ContractSendSecretReveal(_)]));
+        let mediator_state = transition.new_state.unwrap();
+        assert_eq!(
+            mediator_state.transfers_pair[0].payer_state,
+            PayerStatus::WaitingSecretReveal
+        );
+
+        let transition = onchain_secret_reveal(mediator_state, &mut channels, 46);
+        let mediator_state = transition.new_state.unwrap();
+        assert_eq!(
+            mediator_state.transfers_pair[0].payer_state,
+            PayerStatus::SecretRevealed
+        );
+        let payer_channel = &channels[&U256::from(PAYER_CHANNEL)];
+        assert!(channel::is_secret_known_onchain(
+            &payer_channel.partner_state,
+            &mediator_state.secrethash
+        ));
+
+        let transition = block(mediator_state, &mut channels, 47);
+        assert!(transition.events.is_empty());
+        let transition = block(transition.new_state.unwrap(), &mut channels, 100);
+        assert!(transition.events.is_empty());
+        assert_eq!(
+            transition.new_state.unwrap().transfers_pair[0].payer_state,
+            PayerStatus::SecretRevealed
+        );
+    }
+
+    #[test]
+    fn waiting_transfer_is_forwarded_once_a_route_is_usable() {
+        let mut channels = channels();
+        let payee_channel = channels.get_mut(&U256::from(PAYEE_CHANNEL)).unwrap();
+        payee_channel.our_state.contract_balance = U256::zero();
+        let transition = init(&mut channels);
+        assert!(transition.events.is_empty());
+        let mediator_state = transition.new_state.unwrap();
+        assert!(mediator_state.waiting_transfer.is_some());
+
+        let transition = block(mediator_state, &mut channels, 2);
+        assert!(transition.events.is_empty());
+
+        let payee_channel = channels.get_mut(&U256::from(PAYEE_CHANNEL)).unwrap();
+        payee_channel.our_state.contract_balance = U256::from(100);
+        let transition = block(transition.new_state.unwrap(), &mut channels, 3);
+        assert!(matches!(transition.events[..], [Event::SendLockedTransfer(_)]));
+        let mediator_state = transition.new_state.unwrap();
+        assert!(mediator_state.waiting_transfer.is_none());
+        assert_eq!(mediator_state.transfers_pair.len(), 1);
+    }
+
+    #[test]
+    fn waiting_transfer_is_not_forwarded_once_waiting_is_unsafe() {
+        let mut channels = channels();
+        let payee_channel = channels.get_mut(&U256::from(PAYEE_CHANNEL)).unwrap();
+        payee_channel.our_state.contract_balance = U256::zero();
+        let mediator_state = init(&mut channels).new_state.unwrap();
+
+        let payee_channel = channels.get_mut(&U256::from(PAYEE_CHANNEL)).unwrap();
+        payee_channel.our_state.contract_balance = U256::from(100);
+        let transition = block(mediator_state, &mut channels, LOCK_EXPIRATION - 10);
+        assert!(transition.events.is_empty());
+        let mediator_state = transition.new_state.unwrap();
+        assert!(mediator_state.waiting_transfer.is_some());
+        assert!(mediator_state.transfers_pair.is_empty());
+    }
+
+    #[test]
+    fn waiting_transfer_fails_its_claim_once_the_payer_lock_expires() {
+        let mut channels = channels();
+        let payee_channel = channels.get_mut(&U256::from(PAYEE_CHANNEL)).unwrap();
+        payee_channel.our_state.contract_balance = U256::zero();
+        let mediator_state = init(&mut channels).new_state.unwrap();
+        let threshold = channel::get_receiver_expiration_threshold(U64::from(LOCK_EXPIRATION)).as_u64();
+
+        let transition = block(mediator_state, &mut channels, threshold);
+        assert!(transition.events.is_empty());
+
+        let transition = block(transition.new_state.unwrap(), &mut channels, threshold + 1);
+        assert!(matches!(transition.events[..], [Event::UnlockClaimFailed(_)]));
+        let mediator_state = transition.new_state.unwrap();
+        assert!(mediator_state.waiting_transfer_expired);
+
+        let transition = block(mediator_state, &mut channels, threshold + 2);
+        assert!(transition.events.is_empty());
+        assert!(transition.new_state.is_some());
+    }
+
+    #[test]
+    fn onchain_secret_reveal_teaches_the_secret() {
+        let mut channels = channels();
+        let mediator_state = init(&mut channels).new_state.unwrap();
+
+        let transition = onchain_secret_reveal(mediator_state, &mut channels, 5);
+        let mediator_state = transition.new_state.unwrap();
+        assert_eq!(mediator_state.secret, Some(secret()));
+        let payee_channel = &channels[&U256::from(PAYEE_CHANNEL)];
+        assert!(channel::is_secret_known_onchain(
+            &payee_channel.our_state,
+            &mediator_state.secrethash
+        ));
+    }
+
+    #[test]
+    fn secret_registered_after_the_expiration_does_not_unlock() {
+        let mut channels = channels();
+        let mediator_state = init(&mut channels).new_state.unwrap();
+
+        let transition = onchain_secret_reveal(mediator_state, &mut channels, LOCK_EXPIRATION + 1);
+        let mediator_state = transition.new_state.unwrap();
+        let payer_channel = &channels[&U256::from(PAYER_CHANNEL)];
+        assert!(!channel::is_secret_known_onchain(
+            &payer_channel.partner_state,
+            &mediator_state.secrethash
+        ));
+
+        let transition = block(mediator_state, &mut channels, 100);
+        assert!(transition
+            .events
+            .iter()
+            .any(|event| matches!(event, Event::UnlockClaimFailed(_))));
+        assert_eq!(
+            transition.new_state.unwrap().transfers_pair[0].payer_state,
+            PayerStatus::Expired
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use crate::utils::keccak256;
+use web3::types::{Address, H256};
+
+pub mod event;
+pub mod initiator;
+pub mod mediator;
+pub mod state;
+pub mod state_change;
+pub mod target;
+
+/// Message identifiers are derived from the payment, the recipient and the kind of message,
+/// so that replaying the state changes of a payment sends the very same messages again.
+pub fn message_identifier(secrethash: H256, recipient: Address, message_kind: &str) -> u64 {
+    let mut data = vec![];
+    data.extend_from_slice(secrethash.as_bytes());
+    data.extend_from_slice(recipient.as_bytes());
+    data.extend_from_slice(message_kind.as_bytes());
+
+    let mut identifier = [0u8; 8];
+    identifier.copy_from_slice(&keccak256(&data).as_bytes()[..8]);
+    u64::from_be_bytes(identifier)
+}
@@ -0,0 +1,143 @@
+use crate::transfer::state::{BalanceProofSignedState, BalanceProofUnsignedState, HashTimeLockState, RouteState};
+use serde::{Deserialize, Serialize};
+use web3::types::{Address, H256, U256};
+
+/// A payment requested by the user, along with the secret which unlocks it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransferDescriptionWithSecretState {
+    pub token_network_registry_address: Address,
+    pub payment_identifier: u64,
+    pub amount: U256,
+    pub token_network_address: Address,
+    pub initiator: Address,
+    pub target: Address,
+    pub secret: H256,
+    pub secrethash: H256,
+}
+
+/// A locked transfer we sent, its balance proof is signed when the message is sent.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LockedTransferUnsignedState {
+    pub payment_identifier: u64,
+    pub token: Address,
+    pub balance_proof: BalanceProofUnsignedState,
+    pub lock: HashTimeLockState,
+    pub initiator: Address,
+    pub target: Address,
+}
+
+/// A locked transfer we received, signed by its sender.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LockedTransferSignedState {
+    pub message_identifier: u64,
+    pub payment_identifier: u64,
+    pub token: Address,
+    pub balance_proof: BalanceProofSignedState,
+    pub lock: HashTimeLockState,
+    pub initiator: Address,
+    pub target: Address,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum InitiatorTransferStatus {
+    Pending,
+    SecretRevealed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InitiatorTransferState {
+    pub route: RouteState,
+    pub transfer_description: TransferDescriptionWithSecretState,
+    pub channel_identifier: U256,
+    pub transfer: LockedTransferUnsignedState,
+    pub received_secret_request: bool,
+    pub transfer_state: InitiatorTransferStatus,
+}
+
+/// A payment we initiated, along with the routes it may take.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InitiatorPaymentState {
+    pub routes: Vec<RouteState>,
+    pub initiator: InitiatorTransferState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PayerStatus {
+    Pending,
+    SecretRevealed,
+    WaitingSecretReveal,
+    BalanceProof,
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PayeeStatus {
+    Pending,
+    SecretRevealed,
+    BalanceProof,
+    Expired,
+}
+
+/// A transfer we received from the payer and the transfer we forwarded to the payee.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MediationPairState {
+    pub payer_transfer: LockedTransferSignedState,
+    pub payee_address: Address,
+    pub payee_transfer: LockedTransferUnsignedState,
+    pub payer_state: PayerStatus,
+    pub payee_state: PayeeStatus,
+}
+
+/// A payment we mediate. A transfer which could not be forwarded waits in
+/// `waiting_transfer` until one of the routes becomes usable or its lock expires.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MediatorTransferState {
+    pub secrethash: H256,
+    pub routes: Vec<RouteState>,
+    pub secret: Option<H256>,
+    pub transfers_pair: Vec<MediationPairState>,
+    pub waiting_transfer: Option<LockedTransferSignedState>,
+    /// Set once the payer lock of `waiting_transfer` expired and the failed claim was reported.
+    #[serde(default)]
+    pub waiting_transfer_expired: bool,
+}
+
+impl MediatorTransferState {
+    pub fn new(secrethash: H256, routes: Vec<RouteState>) -> MediatorTransferState {
+        MediatorTransferState {
+            secrethash,
+            routes,
+            secret: None,
+            transfers_pair: vec![],
+            waiting_transfer: None,
+            waiting_transfer_expired: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TargetStatus {
+    SecretRequest,
+    OffchainSecretReveal,
+    OnchainSecretReveal,
+    OnchainUnlock,
+    Expired,
+}
+
+/// A payment addressed to us.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TargetTransferState {
+    pub transfer: LockedTransferSignedState,
+    pub secret: Option<H256>,
+    pub state: TargetStatus,
+}
+
+impl TargetTransferState {
+    pub fn new(transfer: LockedTransferSignedState) -> TargetTransferState {
+        TargetTransferState {
+            transfer,
+            secret: None,
+            state: TargetStatus::SecretRequest,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::transfer::mediated_transfer::state::{LockedTransferSignedState, TransferDescriptionWithSecretState};
+use crate::transfer::state::{BalanceProofSignedState, RouteState};
+use serde::{Deserialize, Serialize};
+use web3::types::{Address, H256, U256, U64};
+
+/// The user asked to send a payment over one of `routes`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActionInitInitiator {
+    pub transfer: TransferDescriptionWithSecretState,
+    pub routes: Vec<RouteState>,
+}
+
+impl ActionInitInitiator {
+    pub fn new(transfer: TransferDescriptionWithSecretState, routes: Vec<RouteState>) -> Self {
+        ActionInitInitiator { transfer, routes }
+    }
+}
+
+/// The target of a payment asks for the secret. The sender of received messages is
+/// recovered from their signature before they become state changes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReceiveSecretRequest {
+    pub payment_identifier: u64,
+    pub amount: U256,
+    pub expiration: U64,
+    pub secrethash: H256,
+    pub sender: Address,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReceiveSecretReveal {
+    pub secret: H256,
+    pub secrethash: H256,
+    pub sender: Address,
+}
+
+/// A locked transfer we have to forward over one of `routes`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActionInitMediator {
+    pub routes: Vec<RouteState>,
+    pub from_transfer: LockedTransferSignedState,
+}
+
+impl ActionInitMediator {
+    pub fn new(routes: Vec<RouteState>, from_transfer: LockedTransferSignedState) -> Self {
+        ActionInitMediator { routes, from_transfer }
+    }
+}
+
+/// The sender of a lock unlocked it, the signer of `balance_proof` is the sender.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReceiveUnlock {
+    pub message_identifier: u64,
+    pub secret: H256,
+    pub secrethash: H256,
+    pub balance_proof: BalanceProofSignedState,
+}
+
+/// The sender of a lock removed it after it expired.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReceiveLockExpired {
+    pub message_identifier: u64,
+    pub secrethash: H256,
+    pub balance_proof: BalanceProofSignedState,
+}
+
+/// A locked transfer addressed to us.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActionInitTarget {
+    pub transfer: LockedTransferSignedState,
+}
+
+impl ActionInitTarget {
+    pub fn new(transfer: LockedTransferSignedState) -> Self {
+        ActionInitTarget { transfer }
+    }
+}
@@ -0,0 +1,521 @@
+use crate::enums::{Event, StateChange};
+use crate::errors::StateTransitionError;
+use crate::transfer::channel;
+use crate::transfer::mediated_transfer::{
+    self, event,
+    mediator::is_safe_to_wait,
+    state::{TargetStatus, TargetTransferState},
+    state_change::{ActionInitTarget, ReceiveLockExpired, ReceiveSecretReveal, ReceiveUnlock},
+};
+use crate::transfer::state::ChannelState;
+use crate::transfer::state_change::{Block, ContractReceiveSecretReveal};
+use crate::utils::sha256_secrethash;
+use web3::types::U64;
+
+pub struct TargetTransition {
+    /// `None` once the transfer was unlocked or its lock removed.
+    pub new_state: Option<TargetTransferState>,
+    pub events: Vec<Event>,
+}
+
+fn unlock_claim_failed(target_state: &TargetTransferState, reason: &str) -> Event {
+    Event::UnlockClaimFailed(event::UnlockClaimFailed {
+        identifier: target_state.transfer.payment_identifier,
+        secrethash: target_state.transfer.lock.secrethash,
+        reason: reason.to_string(),
+    })
+}
+
+/// Accepts the lock of a transfer addressed to us and asks the initiator for the secret.
+/// A lock too close to its expiration is left to expire, the task still has to remove it then.
+fn handle_init(
+    state_change: ActionInitTarget,
+    channel_state: &mut ChannelState,
+    block_number: U64,
+) -> Result<TargetTransition, StateTransitionError> {
+    let transfer = state_change.transfer;
+    let invalid_transfer = if transfer.target != channel_state.our_state.address {
+        Some(String::from("Transfer is not addressed to us"))
+    } else {
+        channel::handle_receive_lockedtransfer(channel_state, &transfer)
+            .err()
+            .map(|e| e.msg)
+    };
+    if let Some(reason) = invalid_transfer {
+        let invalid_locked_transfer = event::InvalidReceivedLockedTransfer {
+            payment_identifier: transfer.payment_identifier,
+            reason,
+        };
+        return Ok(TargetTransition {
+            new_state: None,
+            events: vec![Event::InvalidReceivedLockedTransfer(invalid_locked_transfer)],
+        });
+    }
+
+    let lock = &transfer.lock;
+    let mut events = vec![];
+    if is_safe_to_wait(lock.expiration, channel_state.reveal_timeout, block_number) {
+        let recipient = transfer.initiator;
+        events.push(Event::SendSecretRequest(event::SendSecretRequest {
+            recipient,
+            message_identifier: mediated_transfer::message_identifier(lock.secrethash, recipient, "SecretRequest"),
+            payment_identifier: transfer.payment_identifier,
+            amount: lock.amount,
+            expiration: lock.expiration,
+            secrethash: lock.secrethash,
+        }));
+    }
+
+    Ok(TargetTransition {
+        new_state: Some(TargetTransferState::new(transfer)),
+        events,
+    })
+}
+
+/// Registers the secret on-chain once the lock gets too close to its expiration to wait
+/// for the payer's unlock.
+fn handle_block(
+    mut target_state: TargetTransferState,
+    state_change: Block,
+    channel_state: &mut ChannelState,
+) -> Result<TargetTransition, StateTransitionError> {
+    let block_number = state_change.block_number;
+    let lock = &target_state.transfer.lock;
+    let lock_expiration_threshold = channel::get_receiver_expiration_threshold(lock.expiration);
+    let lock_has_expired = channel::is_lock_expired(
+        &channel_state.partner_state,
+        lock,
+        block_number,
+        lock_expiration_threshold,
+    );
+
+    let mut events = vec![];
+    if lock_has_expired && target_state.state != TargetStatus::Expired {
+        target_state.state = TargetStatus::Expired;
+        events.push(unlock_claim_failed(&target_state, "Lock expired"));
+    } else if let Some(secret) = target_state.secret {
+        let safe_to_wait = is_safe_to_wait(lock.expiration, channel_state.reveal_timeout, block_number);
+        let secret_known = channel::is_secret_known(&channel_state.partner_state, &lock.secrethash);
+        let has_onchain_reveal_started = match target_state.state {
+            TargetStatus::OnchainSecretReveal | TargetStatus::OnchainUnlock => true,
+            TargetStatus::SecretRequest | TargetStatus::OffchainSecretReveal | TargetStatus::Expired => false,
+        };
+        if !safe_to_wait && secret_known && !has_onchain_reveal_started && block_number < lock.expiration {
+            events.push(Event::ContractSendSecretReveal(event::ContractSendSecretReveal {
+                expiration: lock.expiration,
+                secret,
+            }));
+            target_state.state = TargetStatus::OnchainSecretReveal;
+        }
+    }
+
+    Ok(TargetTransition {
+        new_state: Some(target_state),
+        events,
+    })
+}
+
+/// The initiator revealed the secret, revealing it backwards to the payer asks for its unlock.
+fn handle_offchain_secretreveal(
+    mut target_state: TargetTransferState,
+    state_change: ReceiveSecretReveal,
+    channel_state: &mut ChannelState,
+    block_number: U64,
+) -> Result<TargetTransition, StateTransitionError> {
+    let lock = &target_state.transfer.lock;
+    let valid_secret =
+        state_change.secrethash == lock.secrethash && sha256_secrethash(&state_change.secret) == lock.secrethash;
+    let has_transfer_expired =
+        channel::is_lock_expired(&channel_state.partner_state, lock, block_number, lock.expiration);
+    if !valid_secret || has_transfer_expired || target_state.secret.is_some() {
+        return Ok(TargetTransition {
+            new_state: Some(target_state),
+            events: vec![],
+        });
+    }
+
+    let secrethash = lock.secrethash;
+    channel::register_offchain_secret(channel_state, state_change.secret, secrethash);
+    target_state.secret = Some(state_change.secret);
+    target_state.state = TargetStatus::OffchainSecretReveal;
+
+    let recipient = target_state.transfer.balance_proof.sender;
+    let secret_reveal = event::SendSecretReveal {
+        recipient,
+        message_identifier: mediated_transfer::message_identifier(secrethash, recipient, "RevealSecret"),
+        secret: state_change.secret,
+        secrethash,
+    };
+
+    Ok(TargetTransition {
+        new_state: Some(target_state),
+        events: vec![Event::SendSecretReveal(secret_reveal)],
+    })
+}
+
+/// The secret was registered on-chain, either by us once the lock got too close to its
+/// expiration or by another participant of the payment. A lock which had not expired by the
+/// block of the registration can be unlocked on-chain, even without the payer's unlock.
+fn handle_onchain_secretreveal(
+    mut target_state: TargetTransferState,
+    state_change: ContractReceiveSecretReveal,
+    channel_state: &mut ChannelState,
+) -> Result<TargetTransition, StateTransitionError> {
+    let secrethash = target_state.transfer.lock.secrethash;
+    let valid_secret = state_change.secrethash == secrethash && sha256_secrethash(&state_change.secret) == secrethash;
+    if valid_secret {
+        channel::register_onchain_secret(
+            channel_state,
+            state_change.secret,
+            secrethash,
+            state_change.block_number,
+        );
+    }
+    if !valid_secret || !channel::is_secret_known_onchain(&channel_state.partner_state, &secrethash) {
+        return Ok(TargetTransition {
+            new_state: Some(target_state),
+            events: vec![],
+        });
+    }
+
+    target_state.secret = Some(state_change.secret);
+    target_state.state = TargetStatus::OnchainUnlock;
+
+    Ok(TargetTransition {
+        new_state: Some(target_state),
+        events: vec![],
+    })
+}
+
+fn handle_unlock(
+    target_state: TargetTransferState,
+    state_change: ReceiveUnlock,
+    channel_state: &mut ChannelState,
+) -> Result<TargetTransition, StateTransitionError> {
+    if let Err(e) = channel::handle_unlock(channel_state, &state_change) {
+        let invalid_unlock = event::InvalidReceivedUnlock {
+            secrethash: target_state.transfer.lock.secrethash,
+            reason: e.msg,
+        };
+        return Ok(TargetTransition {
+            new_state: Some(target_state),
+            events: vec![Event::InvalidReceivedUnlock(invalid_unlock)],
+        });
+    }
+
+    let transfer = &target_state.transfer;
+    let payment_received_success = event::PaymentReceivedSuccess {
+        token_network_registry_address: channel_state.token_network_registry_address,
+        token_network_address: channel_state.canonical_identifier.token_network_address,
+        identifier: transfer.payment_identifier,
+        amount: transfer.lock.amount,
+        initiator: transfer.initiator,
+    };
+    let unlock_claim_success = event::UnlockClaimSuccess {
+        identifier: transfer.payment_identifier,
+        secrethash: transfer.lock.secrethash,
+    };
+
+    Ok(TargetTransition {
+        new_state: None,
+        events: vec![
+            Event::PaymentReceivedSuccess(payment_received_success),
+            Event::UnlockClaimSuccess(unlock_claim_success),
+        ],
+    })
+}
+
+fn handle_lock_expired(
+    target_state: TargetTransferState,
+    state_change: ReceiveLockExpired,
+    channel_state: &mut ChannelState,
+    block_number: U64,
+) -> Result<TargetTransition, StateTransitionError> {
+    if let Err(e) = channel::handle_receive_lock_expired(channel_state, &state_change, block_number) {
+        let invalid_lock_expired = event::InvalidReceivedLockExpired {
+            secrethash: target_state.transfer.lock.secrethash,
+            reason: e.msg,
+        };
+        return Ok(TargetTransition {
+            new_state: Some(target_state),
+            events: vec![Event::InvalidReceivedLockExpired(invalid_lock_expired)],
+        });
+    }
+
+    // The failure was already reported if the lock expired on a block before.
+    let events = if target_state.state == TargetStatus::Expired {
+        vec![]
+    } else {
+        vec![unlock_claim_failed(&target_state, "Lock expired")]
+    };
+
+    Ok(TargetTransition {
+        new_state: None,
+        events,
+    })
+}
+
+pub fn state_transition(
+    target_state: Option<TargetTransferState>,
+    state_change: StateChange,
+    channel_state: &mut ChannelState,
+    block_number: U64,
+) -> Result<TargetTransition, StateTransitionError> {
+    match (target_state, state_change) {
+        (None, StateChange::ActionInitTarget(state_change)) => handle_init(state_change, channel_state, block_number),
+        (Some(target_state), StateChange::Block(state_change)) => {
+            handle_block(target_state, state_change, channel_state)
+        }
+        (Some(target_state), StateChange::ReceiveSecretReveal(state_change)) => {
+            handle_offchain_secretreveal(target_state, state_change, channel_state, block_number)
+        }
+        (Some(target_state), StateChange::ContractReceiveSecretReveal(state_change)) => {
+            handle_onchain_secretreveal(target_state, state_change, channel_state)
+        }
+        (Some(target_state), StateChange::ReceiveUnlock(state_change)) => {
+            handle_unlock(target_state, state_change, channel_state)
+        }
+        (Some(target_state), StateChange::ReceiveLockExpired(state_change)) => {
+            handle_lock_expired(target_state, state_change, channel_state, block_number)
+        }
+        _ => Err(StateTransitionError {
+            msg: String::from("Could not transition target"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::factories::{
+        make_address, make_block, make_default_channel_state, make_received_transfer, make_signed_balance_proof,
+        our_address, partner_address, secret, target_address,
+    };
+    use crate::transfer::state::{BalanceProofSignedState, PendingLocksState};
+    use web3::types::{Address, H256, U256};
+
+    const LOCK_EXPIRATION: u64 = 50;
+
+    fn secrethash() -> H256 {
+        sha256_secrethash(&secret())
+    }
+
+    fn init(channel_state: &mut ChannelState, target: Address, block_number: u64) -> TargetTransition {
+        let transfer = make_received_transfer(channel_state, 10, LOCK_EXPIRATION, secret(), target);
+        let state_change = StateChange::ActionInitTarget(ActionInitTarget::new(transfer));
+        state_transition(None, state_change, channel_state, U64::from(block_number)).unwrap()
+    }
+
+    fn block(
+        target_state: TargetTransferState,
+        channel_state: &mut ChannelState,
+        block_number: u64,
+    ) -> TargetTransition {
+        let block_number = U64::from(block_number);
+        state_transition(
+            Some(target_state),
+            make_block(block_number),
+            channel_state,
+            block_number,
+        )
+        .unwrap()
+    }
+
+    fn secret_reveal(
+        target_state: TargetTransferState,
+        channel_state: &mut ChannelState,
+        secret: H256,
+    ) -> TargetTransition {
+        let state_change = StateChange::ReceiveSecretReveal(ReceiveSecretReveal {
+            secret,
+            secrethash: secrethash(),
+            sender: partner_address(),
+        });
+        state_transition(Some(target_state), state_change, channel_state, U64::from(2)).unwrap()
+    }
+
+    fn onchain_secret_reveal(
+        target_state: TargetTransferState,
+        channel_state: &mut ChannelState,
+        block_number: u64,
+    ) -> TargetTransition {
+        let state_change = StateChange::ContractReceiveSecretReveal(ContractReceiveSecretReveal::new(
+            H256::zero(),
+            make_address(2000),
+            secrethash(),
+            secret(),
+            U64::from(block_number),
+            H256::zero(),
+        ));
+        state_transition(Some(target_state), state_change, channel_state, U64::from(block_number)).unwrap()
+    }
+
+    /// The balance proof of the payer which removes the lock from its pending locks.
+    fn remove_lock_balance_proof(channel_state: &ChannelState, transferred_amount: u64) -> BalanceProofSignedState {
+        let locksroot = channel::compute_locksroot(&PendingLocksState { locks: vec![] });
+        make_signed_balance_proof(
+            channel_state,
+            partner_address(),
+            2,
+            U256::from(transferred_amount),
+            U256::zero(),
+            locksroot,
+        )
+    }
+
+    #[test]
+    fn init_requests_the_secret() {
+        let mut channel_state = make_default_channel_state();
+        let transition = init(&mut channel_state, our_address(), 1);
+
+        assert!(transition.new_state.is_some());
+        match &transition.events[..] {
+            [Event::SendSecretRequest(secret_request)] => {
+                assert_eq!(secret_request.recipient, partner_address());
+                assert_eq!(secret_request.amount, U256::from(10));
+                assert_eq!(secret_request.expiration, U64::from(LOCK_EXPIRATION));
+            }
+            events => panic!("Unexpected events {:?}", events),
+        }
+        assert!(channel_state
+            .partner_state
+            .secrethashes_to_lockedlocks
+            .contains_key(&secrethash()));
+    }
+
+    #[test]
+    fn init_rejects_a_transfer_for_someone_else() {
+        let mut channel_state = make_default_channel_state();
+        let transition = init(&mut channel_state, target_address(), 1);
+
+        assert!(transition.new_state.is_none());
+        assert!(matches!(
+            transition.events[..],
+            [Event::InvalidReceivedLockedTransfer(_)]
+        ));
+        assert!(channel_state.partner_state.secrethashes_to_lockedlocks.is_empty());
+    }
+
+    #[test]
+    fn init_does_not_request_the_secret_of_a_lock_about_to_expire() {
+        let mut channel_state = make_default_channel_state();
+        let transition = init(&mut channel_state, our_address(), LOCK_EXPIRATION - 10);
+
+        assert!(transition.new_state.is_some());
+        assert!(transition.events.is_empty());
+    }
+
+    #[test]
+    fn secret_reveal_is_revealed_to_the_payer() {
+        let mut channel_state = make_default_channel_state();
+        let target_state = init(&mut channel_state, our_address(), 1).new_state.unwrap();
+
+        let transition = secret_reveal(target_state, &mut channel_state, H256::from_low_u64_be(43));
+        assert!(transition.events.is_empty());
+
+        let transition = secret_reveal(transition.new_state.unwrap(), &mut channel_state, secret());
+        match &transition.events[..] {
+            [Event::SendSecretReveal(secret_reveal)] => assert_eq!(secret_reveal.recipient, partner_address()),
+            events => panic!("Unexpected events {:?}", events),
+        }
+        let target_state = transition.new_state.unwrap();
+        assert_eq!(target_state.state, TargetStatus::OffchainSecretReveal);
+        assert!(channel::is_secret_known(&channel_state.partner_state, &secrethash()));
+    }
+
+    #[test]
+    fn unlock_completes_the_payment() {
+        let mut channel_state = make_default_channel_state();
+        let target_state = init(&mut channel_state, our_address(), 1).new_state.unwrap();
+        let target_state = secret_reveal(target_state, &mut channel_state, secret())
+            .new_state
+            .unwrap();
+
+        let state_change = StateChange::ReceiveUnlock(ReceiveUnlock {
+            message_identifier: 1,
+            secret: secret(),
+            secrethash: secrethash(),
+            balance_proof: remove_lock_balance_proof(&channel_state, 10),
+        });
+        let transition = state_transition(Some(target_state), state_change, &mut channel_state, U64::from(3)).unwrap();
+        assert!(transition.new_state.is_none());
+        assert!(matches!(
+            transition.events[..],
+            [Event::PaymentReceivedSuccess(_), Event::UnlockClaimSuccess(_)]
+        ));
+        assert_eq!(
+            channel::get_transferred_amount(&channel_state.partner_state),
+            U256::from(10)
+        );
+        assert!(!channel::lock_exists_in_either_channel_side(
+            &channel_state,
+            &secrethash()
+        ));
+    }
+
+    #[test]
+    fn lock_expires_at_the_receiver_expiration_threshold() {
+        let mut channel_state = make_default_channel_state();
+        let target_state = init(&mut channel_state, our_address(), 1).new_state.unwrap();
+        let threshold = channel::get_receiver_expiration_threshold(U64::from(LOCK_EXPIRATION)).as_u64();
+
+        let transition = block(target_state, &mut channel_state, threshold);
+        assert!(transition.events.is_empty());
+        let transition = block(transition.new_state.unwrap(), &mut channel_state, threshold + 1);
+        assert!(matches!(transition.events[..], [Event::UnlockClaimFailed(_)]));
+        let target_state = transition.new_state.unwrap();
+        assert_eq!(target_state.state, TargetStatus::Expired);
+
+        let state_change = StateChange::ReceiveLockExpired(ReceiveLockExpired {
+            message_identifier: 1,
+            secrethash: secrethash(),
+            balance_proof: remove_lock_balance_proof(&channel_state, 0),
+        });
+        let block_number = U64::from(threshold + 1);
+        let transition = state_transition(Some(target_state), state_change, &mut channel_state, block_number).unwrap();
+        assert!(transition.new_state.is_none());
+        assert!(transition.events.is_empty());
+        assert!(!channel::lock_exists_in_either_channel_side(
+            &channel_state,
+            &secrethash()
+        ));
+    }
+
+    #[test]
+    fn onchain_secret_reveal_keeps_the_lock_from_expiring() {
+        let mut channel_state = make_default_channel_state();
+        let target_state = init(&mut channel_state, our_address(), 1).new_state.unwrap();
+        let target_state = secret_reveal(target_state, &mut channel_state, secret())
+            .new_state
+            .unwrap();
+
+        let transition = block(target_state, &mut channel_state, LOCK_EXPIRATION - 5);
+        assert!(matches!(transition.events[..], [Event::ContractSendSecretReveal(_)]));
+        let target_state = transition.new_state.unwrap();
+        assert_eq!(target_state.state, TargetStatus::OnchainSecretReveal);
+
+        let transition = onchain_secret_reveal(target_state, &mut channel_state, LOCK_EXPIRATION - 4);
+        let target_state = transition.new_state.unwrap();
+        assert_eq!(target_state.state, TargetStatus::OnchainUnlock);
+        assert!(channel::is_secret_known_onchain(
+            &channel_state.partner_state,
+            &secrethash()
+        ));
+
+        let transition = block(target_state, &mut channel_state, LOCK_EXPIRATION + 100);
+        assert!(transition.events.is_empty());
+        assert_eq!(transition.new_state.unwrap().state, TargetStatus::OnchainUnlock);
+    }
+
+    #[test]
+    fn secret_registered_after_the_expiration_is_ignored() {
+        let mut channel_state = make_default_channel_state();
+        let target_state = init(&mut channel_state, our_address(), 1).new_state.unwrap();
+
+        let transition = onchain_secret_reveal(target_state, &mut channel_state, LOCK_EXPIRATION + 1);
+        let target_state = transition.new_state.unwrap();
+        assert_eq!(target_state.state, TargetStatus::SecretRequest);
+        assert!(target_state.secret.is_none());
+        assert!(!channel::is_secret_known(&channel_state.partner_state, &secrethash()));
+    }
+}
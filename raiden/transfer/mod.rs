@@ -1,5 +1,9 @@
 pub mod chain;
+pub mod channel;
 pub mod event;
+#[cfg(test)]
+pub(crate) mod factories;
+pub mod mediated_transfer;
 pub mod state;
 pub mod state_change;
 pub mod token_network;
@@ -1,10 +1,13 @@
+use crate::constants::LOCKSROOT_OF_NO_LOCKS;
 use crate::enums::ChainID;
 use crate::errors::ChannelError;
+use crate::transfer::mediated_transfer::state::{InitiatorPaymentState, MediatorTransferState, TargetTransferState};
+use crate::utils::{keccak256, u256_to_bytes, u64_to_bytes};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use web3::types::{Address, H256, U256, U64};
+use web3::types::{Address, Bytes, H256, U256, U64};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct CanonicalIdentifier {
     pub chain_identifier: u64,
     pub token_network_address: Address,
@@ -15,8 +18,11 @@ pub struct CanonicalIdentifier {
 pub struct ChainState {
     pub chain_id: ChainID,
     pub block_number: U64,
+    pub confirmed_block_number: U64,
     pub our_address: Address,
     pub identifiers_to_tokennetworkregistries: HashMap<Address, TokenNetworkRegistryState>,
+    #[serde(default)]
+    pub payment_mapping: PaymentMappingState,
 }
 
 impl ChainState {
@@ -24,12 +30,45 @@ impl ChainState {
         ChainState {
             chain_id,
             block_number,
+            confirmed_block_number: block_number,
             our_address,
             identifiers_to_tokennetworkregistries: HashMap::new(),
+            payment_mapping: PaymentMappingState::default(),
         }
     }
 }
 
+/// The payments in flight, each one is a task driven by the state changes of its secrethash.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PaymentMappingState {
+    pub secrethashes_to_task: HashMap<H256, TransferTask>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum TransferTask {
+    Initiator(InitiatorTask),
+    Mediator(MediatorTask),
+    Target(TargetTask),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InitiatorTask {
+    pub token_network_address: Address,
+    pub manager_state: InitiatorPaymentState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MediatorTask {
+    pub token_network_address: Address,
+    pub mediator_state: MediatorTransferState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TargetTask {
+    pub canonical_identifier: CanonicalIdentifier,
+    pub target_state: TargetTransferState,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TokenNetworkRegistryState {
     pub address: Address,
@@ -65,8 +104,8 @@ pub struct TokenNetworkState {
     pub address: Address,
     pub token_address: Address,
     pub network_graph: TokenNetworkGraphState,
-    pub channelidentifiers_to_channels: HashMap<u64, ChannelState>,
-    pub partneraddresses_to_channelidentifiers: HashMap<Address, Vec<u64>>,
+    pub channelidentifiers_to_channels: HashMap<U256, ChannelState>,
+    pub partneraddresses_to_channelidentifiers: HashMap<Address, Vec<U256>>,
 }
 
 impl TokenNetworkState {
@@ -81,12 +120,87 @@ impl TokenNetworkState {
     }
 }
 
+/// Channels of all participants of a token network. Nodes map each participant to the
+/// identifiers of its channels, edges map each channel identifier to its participants.
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct TokenNetworkGraphState {}
+pub struct TokenNetworkGraphState {
+    #[serde(default)]
+    pub nodes: HashMap<Address, Vec<U256>>,
+    #[serde(default)]
+    pub edges: HashMap<U256, ChannelEdgeState>,
+}
 
 impl TokenNetworkGraphState {
     pub fn default() -> TokenNetworkGraphState {
-        TokenNetworkGraphState {}
+        TokenNetworkGraphState {
+            nodes: HashMap::new(),
+            edges: HashMap::new(),
+        }
+    }
+
+    pub fn add_channel(&mut self, channel_identifier: U256, participant1: Address, participant2: Address) {
+        for participant in &[participant1, participant2] {
+            let channel_identifiers = self.nodes.entry(*participant).or_default();
+            if !channel_identifiers.contains(&channel_identifier) {
+                channel_identifiers.push(channel_identifier);
+            }
+        }
+        self.edges.insert(
+            channel_identifier,
+            ChannelEdgeState {
+                participant1,
+                participant2,
+                status: ChannelEdgeStatus::Opened,
+            },
+        );
+    }
+
+    pub fn close_channel(&mut self, channel_identifier: U256) {
+        if let Some(edge) = self.edges.get_mut(&channel_identifier) {
+            edge.status = ChannelEdgeStatus::Closed;
+        }
+    }
+
+    /// Removes a settled channel, along with participants which have no channel left.
+    pub fn remove_channel(&mut self, channel_identifier: U256) {
+        let edge = match self.edges.remove(&channel_identifier) {
+            Some(edge) => edge,
+            None => return,
+        };
+        for participant in &[edge.participant1, edge.participant2] {
+            if let Some(channel_identifiers) = self.nodes.get_mut(participant) {
+                channel_identifiers.retain(|identifier| *identifier != channel_identifier);
+                if channel_identifiers.is_empty() {
+                    self.nodes.remove(participant);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChannelEdgeStatus {
+    Opened,
+    Closed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChannelEdgeState {
+    pub participant1: Address,
+    pub participant2: Address,
+    pub status: ChannelEdgeStatus,
+}
+
+impl ChannelEdgeState {
+    /// The participant on the other end of the channel from `participant`.
+    pub fn partner_of(&self, participant: Address) -> Option<Address> {
+        if participant == self.participant1 {
+            Some(self.participant2)
+        } else if participant == self.participant2 {
+            Some(self.participant1)
+        } else {
+            None
+        }
     }
 }
 
@@ -143,26 +257,26 @@ impl ChannelState {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct OurEndState {
-    address: Address,
-    contract_balance: u64,
-    onchain_total_withdraw: u64,
-    withdraws_pending: HashMap<u64, PendingWithdrawState>,
-    withdraws_expired: Vec<ExpiredWithdrawState>,
-    secrethashes_to_lockedlocks: HashMap<H256, HashTimeLockState>,
-    secrethashes_to_unlockedlocks: HashMap<H256, UnlockPartialProofState>,
-    secrethashes_to_onchain_unlockedlocks: HashMap<H256, UnlockPartialProofState>,
-    balance_proof: Option<BalanceProofUnsignedState>,
-    pending_locks: PendingLocksState,
-    onchain_locksroot: H256,
-    nonce: u64,
+    pub address: Address,
+    pub contract_balance: U256,
+    pub onchain_total_withdraw: U256,
+    pub withdraws_pending: HashMap<u64, PendingWithdrawState>,
+    pub withdraws_expired: Vec<ExpiredWithdrawState>,
+    pub secrethashes_to_lockedlocks: HashMap<H256, HashTimeLockState>,
+    pub secrethashes_to_unlockedlocks: HashMap<H256, UnlockPartialProofState>,
+    pub secrethashes_to_onchain_unlockedlocks: HashMap<H256, UnlockPartialProofState>,
+    pub balance_proof: Option<BalanceProofUnsignedState>,
+    pub pending_locks: PendingLocksState,
+    pub onchain_locksroot: H256,
+    pub nonce: u64,
 }
 
 impl OurEndState {
     pub fn new(address: Address) -> OurEndState {
         OurEndState {
             address,
-            contract_balance: 0,
-            onchain_total_withdraw: 0,
+            contract_balance: U256::zero(),
+            onchain_total_withdraw: U256::zero(),
             withdraws_pending: HashMap::new(),
             withdraws_expired: vec![],
             secrethashes_to_lockedlocks: HashMap::new(),
@@ -178,26 +292,26 @@ impl OurEndState {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PartnerEndState {
-    address: Address,
-    contract_balance: u64,
-    onchain_total_withdraw: u64,
-    withdraws_pending: HashMap<u16, PendingWithdrawState>,
-    withdraws_expired: Vec<ExpiredWithdrawState>,
-    secrethashes_to_lockedlocks: HashMap<H256, HashTimeLockState>,
-    secrethashes_to_unlockedlocks: HashMap<H256, UnlockPartialProofState>,
-    secrethashes_to_onchain_unlockedlocks: HashMap<H256, UnlockPartialProofState>,
-    balance_proof: Option<BalanceProofSignedState>,
-    pending_locks: PendingLocksState,
-    onchain_locksroot: H256,
-    nonce: u64,
+    pub address: Address,
+    pub contract_balance: U256,
+    pub onchain_total_withdraw: U256,
+    pub withdraws_pending: HashMap<u16, PendingWithdrawState>,
+    pub withdraws_expired: Vec<ExpiredWithdrawState>,
+    pub secrethashes_to_lockedlocks: HashMap<H256, HashTimeLockState>,
+    pub secrethashes_to_unlockedlocks: HashMap<H256, UnlockPartialProofState>,
+    pub secrethashes_to_onchain_unlockedlocks: HashMap<H256, UnlockPartialProofState>,
+    pub balance_proof: Option<BalanceProofSignedState>,
+    pub pending_locks: PendingLocksState,
+    pub onchain_locksroot: H256,
+    pub nonce: u64,
 }
 
 impl PartnerEndState {
     pub fn new(address: Address) -> PartnerEndState {
         PartnerEndState {
             address,
-            contract_balance: 0,
-            onchain_total_withdraw: 0,
+            contract_balance: U256::zero(),
+            onchain_total_withdraw: U256::zero(),
             withdraws_pending: HashMap::new(),
             withdraws_expired: vec![],
             secrethashes_to_lockedlocks: HashMap::new(),
@@ -211,32 +325,163 @@ impl PartnerEndState {
     }
 }
 
+/// Both ends of a channel hold their balances and locks the same way, only the
+/// kind of balance proof differs.
+pub trait EndState {
+    fn address(&self) -> Address;
+    fn contract_balance(&self) -> U256;
+    fn onchain_total_withdraw(&self) -> U256;
+    fn transferred_amount(&self) -> U256;
+    fn locked_locks(&self) -> &HashMap<H256, HashTimeLockState>;
+    fn unlocked_locks(&self) -> &HashMap<H256, UnlockPartialProofState>;
+    fn onchain_unlocked_locks(&self) -> &HashMap<H256, UnlockPartialProofState>;
+    /// Forgets the lock of `secrethash` once it was unlocked or expired.
+    fn del_lock(&mut self, secrethash: &H256);
+}
+
+impl EndState for OurEndState {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn contract_balance(&self) -> U256 {
+        self.contract_balance
+    }
+
+    fn onchain_total_withdraw(&self) -> U256 {
+        self.onchain_total_withdraw
+    }
+
+    fn transferred_amount(&self) -> U256 {
+        self.balance_proof
+            .as_ref()
+            .map(|balance_proof| balance_proof.transferred_amount)
+            .unwrap_or_else(U256::zero)
+    }
+
+    fn locked_locks(&self) -> &HashMap<H256, HashTimeLockState> {
+        &self.secrethashes_to_lockedlocks
+    }
+
+    fn unlocked_locks(&self) -> &HashMap<H256, UnlockPartialProofState> {
+        &self.secrethashes_to_unlockedlocks
+    }
+
+    fn onchain_unlocked_locks(&self) -> &HashMap<H256, UnlockPartialProofState> {
+        &self.secrethashes_to_onchain_unlockedlocks
+    }
+
+    fn del_lock(&mut self, secrethash: &H256) {
+        self.secrethashes_to_lockedlocks.remove(secrethash);
+        self.secrethashes_to_unlockedlocks.remove(secrethash);
+        self.secrethashes_to_onchain_unlockedlocks.remove(secrethash);
+    }
+}
+
+impl EndState for PartnerEndState {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn contract_balance(&self) -> U256 {
+        self.contract_balance
+    }
+
+    fn onchain_total_withdraw(&self) -> U256 {
+        self.onchain_total_withdraw
+    }
+
+    fn transferred_amount(&self) -> U256 {
+        self.balance_proof
+            .as_ref()
+            .map(|balance_proof| balance_proof.transferred_amount)
+            .unwrap_or_else(U256::zero)
+    }
+
+    fn locked_locks(&self) -> &HashMap<H256, HashTimeLockState> {
+        &self.secrethashes_to_lockedlocks
+    }
+
+    fn unlocked_locks(&self) -> &HashMap<H256, UnlockPartialProofState> {
+        &self.secrethashes_to_unlockedlocks
+    }
+
+    fn onchain_unlocked_locks(&self) -> &HashMap<H256, UnlockPartialProofState> {
+        &self.secrethashes_to_onchain_unlockedlocks
+    }
+
+    fn del_lock(&mut self, secrethash: &H256) {
+        self.secrethashes_to_lockedlocks.remove(secrethash);
+        self.secrethashes_to_unlockedlocks.remove(secrethash);
+        self.secrethashes_to_onchain_unlockedlocks.remove(secrethash);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransactionChannelDeposit {
+    pub participant_address: Address,
+    pub contract_balance: U256,
+    pub deposit_block_number: U64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BalanceProofUnsignedState {
-    nonce: u64,
-    transferred_amount: u64,
-    locked_amount: u64,
-    locksroot: H256,
-    canonical_identifier: CanonicalIdentifier,
-    balance_hash: H256,
+    pub nonce: u64,
+    pub transferred_amount: U256,
+    pub locked_amount: U256,
+    pub locksroot: H256,
+    pub canonical_identifier: CanonicalIdentifier,
+    pub balance_hash: H256,
+}
+
+impl BalanceProofUnsignedState {
+    pub fn new(
+        nonce: u64,
+        transferred_amount: U256,
+        locked_amount: U256,
+        locksroot: H256,
+        canonical_identifier: CanonicalIdentifier,
+    ) -> BalanceProofUnsignedState {
+        BalanceProofUnsignedState {
+            nonce,
+            transferred_amount,
+            locked_amount,
+            locksroot,
+            canonical_identifier,
+            balance_hash: hash_balance_data(transferred_amount, locked_amount, locksroot),
+        }
+    }
+}
+
+/// The balance hash signed in balance proofs, zero for a channel without transfers.
+pub fn hash_balance_data(transferred_amount: U256, locked_amount: U256, locksroot: H256) -> H256 {
+    let no_locks = locksroot.is_zero() || locksroot == LOCKSROOT_OF_NO_LOCKS;
+    if transferred_amount.is_zero() && locked_amount.is_zero() && no_locks {
+        return H256::zero();
+    }
+    let mut data = vec![];
+    data.extend_from_slice(&u256_to_bytes(transferred_amount));
+    data.extend_from_slice(&u256_to_bytes(locked_amount));
+    data.extend_from_slice(locksroot.as_bytes());
+    keccak256(&data)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BalanceProofSignedState {
-    nonce: u64,
-    transferred_amount: u64,
-    locked_amount: u64,
-    locksroot: H256,
-    message_hash: H256,
-    signature: H256,
-    sender: Address,
-    canonical_identifier: CanonicalIdentifier,
-    balance_hash: H256,
+    pub nonce: u64,
+    pub transferred_amount: U256,
+    pub locked_amount: U256,
+    pub locksroot: H256,
+    pub message_hash: H256,
+    pub signature: H256,
+    pub sender: Address,
+    pub canonical_identifier: CanonicalIdentifier,
+    pub balance_hash: H256,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PendingLocksState {
-    locks: Vec<H256>,
+    pub locks: Vec<Bytes>,
 }
 
 impl PendingLocksState {
@@ -247,45 +492,65 @@ impl PendingLocksState {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UnlockPartialProofState {
-    lock: HashTimeLockState,
-    secret: H256,
-    amount: u64,
-    expiration: u16,
-    secrethash: H256,
-    encoded: H256,
+    pub lock: HashTimeLockState,
+    pub secret: H256,
+    pub amount: U256,
+    pub expiration: U64,
+    pub secrethash: H256,
+    pub encoded: Bytes,
+}
+
+impl UnlockPartialProofState {
+    pub fn new(lock: HashTimeLockState, secret: H256) -> UnlockPartialProofState {
+        UnlockPartialProofState {
+            secret,
+            amount: lock.amount,
+            expiration: lock.expiration,
+            secrethash: lock.secrethash,
+            encoded: lock.encoded.clone(),
+            lock,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct HashTimeLockState {
-    amount: u64,
-    expiration: u16,
-    secrethash: H256,
-    encoded: H256,
+    pub amount: U256,
+    pub expiration: U64,
+    pub secrethash: H256,
+    pub encoded: Bytes,
 }
 
 impl HashTimeLockState {
-    pub fn new(amount: u64, expiration: u16, secrethash: H256, encoded: H256) -> HashTimeLockState {
+    /// Locks are encoded the way the token network contract expects them in the pending locks
+    /// of an unlock: expiration, amount and secrethash, each taking 32 bytes.
+    pub fn new(amount: U256, expiration: U64, secrethash: H256) -> HashTimeLockState {
+        let mut encoded = vec![];
+        encoded.extend_from_slice(&u64_to_bytes(expiration));
+        encoded.extend_from_slice(&u256_to_bytes(amount));
+        encoded.extend_from_slice(secrethash.as_bytes());
+
         HashTimeLockState {
             amount,
             expiration,
             secrethash,
-            encoded,
+            encoded: Bytes(encoded),
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ExpiredWithdrawState {
-    total_withdraw: u64,
-    expiration: u16,
-    nonce: u64,
+    pub total_withdraw: u64,
+    pub expiration: u16,
+    pub nonce: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PendingWithdrawState {
-    total_withdraw: u64,
-    expiration: u16,
-    nonce: u64,
+    pub total_withdraw: u64,
+    pub expiration: u16,
+    pub nonce: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -296,7 +561,30 @@ pub struct FeeScheduleState {
     penalty_func: Option<u64>,
 }
 
+/// A path through the network, starting with our address and ending with the target.
 #[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RouteState {
+    pub route: Vec<Address>,
+    pub forward_channel_id: U256,
+}
+
+impl RouteState {
+    pub fn next_hop_address(&self) -> Option<Address> {
+        self.route.get(1).cloned()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChannelStatus {
+    Opened,
+    Closing,
+    Closed,
+    Settling,
+    Settled,
+    Unusable,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum TransactionResult {
     SUCCESS,
     FAILURE,
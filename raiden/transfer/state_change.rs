@@ -1,17 +1,24 @@
 use crate::enums::ChainID;
-use crate::transfer::state::{ChannelState, TokenNetworkRegistryState, TokenNetworkState};
+use crate::transfer::state::{
+    CanonicalIdentifier, ChannelState, TokenNetworkRegistryState, TokenNetworkState, TransactionChannelDeposit,
+};
 use serde::{Deserialize, Serialize};
-use web3::types::{Address, H256, U64};
+use web3::types::{Address, H256, U256, U64};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Block {
     pub chain_id: ChainID,
     pub block_number: U64,
+    pub confirmed_block_number: U64,
 }
 
 impl Block {
-    pub fn new(chain_id: ChainID, block_number: U64) -> Block {
-        Block { chain_id, block_number }
+    pub fn new(chain_id: ChainID, block_number: U64, confirmed_block_number: U64) -> Block {
+        Block {
+            chain_id,
+            block_number,
+            confirmed_block_number,
+        }
     }
 }
 
@@ -91,3 +98,171 @@ impl ContractReceiveChannelOpened {
         }
     }
 }
+
+/// A channel between two other participants was opened, it only feeds the network graph.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContractReceiveRouteNew {
+    pub transaction_hash: Option<H256>,
+    pub canonical_identifier: CanonicalIdentifier,
+    pub participant1: Address,
+    pub participant2: Address,
+    pub block_number: U64,
+    pub block_hash: H256,
+}
+
+impl ContractReceiveRouteNew {
+    pub fn new(
+        transaction_hash: H256,
+        canonical_identifier: CanonicalIdentifier,
+        participant1: Address,
+        participant2: Address,
+        block_number: U64,
+        block_hash: H256,
+    ) -> Self {
+        ContractReceiveRouteNew {
+            transaction_hash: Some(transaction_hash),
+            canonical_identifier,
+            participant1,
+            participant2,
+            block_number,
+            block_hash,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContractReceiveChannelDeposit {
+    pub transaction_hash: Option<H256>,
+    pub canonical_identifier: CanonicalIdentifier,
+    pub deposit_transaction: TransactionChannelDeposit,
+    pub block_number: U64,
+    pub block_hash: H256,
+}
+
+impl ContractReceiveChannelDeposit {
+    pub fn new(
+        transaction_hash: H256,
+        canonical_identifier: CanonicalIdentifier,
+        deposit_transaction: TransactionChannelDeposit,
+        block_number: U64,
+        block_hash: H256,
+    ) -> Self {
+        ContractReceiveChannelDeposit {
+            transaction_hash: Some(transaction_hash),
+            canonical_identifier,
+            deposit_transaction,
+            block_number,
+            block_hash,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContractReceiveChannelClosed {
+    pub transaction_hash: Option<H256>,
+    pub transaction_from: Address,
+    pub canonical_identifier: CanonicalIdentifier,
+    pub block_number: U64,
+    pub block_hash: H256,
+}
+
+impl ContractReceiveChannelClosed {
+    pub fn new(
+        transaction_hash: H256,
+        transaction_from: Address,
+        canonical_identifier: CanonicalIdentifier,
+        block_number: U64,
+        block_hash: H256,
+    ) -> Self {
+        ContractReceiveChannelClosed {
+            transaction_hash: Some(transaction_hash),
+            transaction_from,
+            canonical_identifier,
+            block_number,
+            block_hash,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContractReceiveUpdateTransfer {
+    pub transaction_hash: Option<H256>,
+    pub canonical_identifier: CanonicalIdentifier,
+    pub nonce: U256,
+    pub block_number: U64,
+    pub block_hash: H256,
+}
+
+impl ContractReceiveUpdateTransfer {
+    pub fn new(
+        transaction_hash: H256,
+        canonical_identifier: CanonicalIdentifier,
+        nonce: U256,
+        block_number: U64,
+        block_hash: H256,
+    ) -> Self {
+        ContractReceiveUpdateTransfer {
+            transaction_hash: Some(transaction_hash),
+            canonical_identifier,
+            nonce,
+            block_number,
+            block_hash,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContractReceiveChannelSettled {
+    pub transaction_hash: Option<H256>,
+    pub canonical_identifier: CanonicalIdentifier,
+    pub block_number: U64,
+    pub block_hash: H256,
+}
+
+impl ContractReceiveChannelSettled {
+    pub fn new(
+        transaction_hash: H256,
+        canonical_identifier: CanonicalIdentifier,
+        block_number: U64,
+        block_hash: H256,
+    ) -> Self {
+        ContractReceiveChannelSettled {
+            transaction_hash: Some(transaction_hash),
+            canonical_identifier,
+            block_number,
+            block_hash,
+        }
+    }
+}
+
+/// A secret was registered in the SecretRegistry, locks which had not expired by
+/// `block_number` can be unlocked on-chain with it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContractReceiveSecretReveal {
+    pub transaction_hash: Option<H256>,
+    pub secret_registry_address: Address,
+    pub secrethash: H256,
+    pub secret: H256,
+    pub block_number: U64,
+    pub block_hash: H256,
+}
+
+impl ContractReceiveSecretReveal {
+    pub fn new(
+        transaction_hash: H256,
+        secret_registry_address: Address,
+        secrethash: H256,
+        secret: H256,
+        block_number: U64,
+        block_hash: H256,
+    ) -> Self {
+        ContractReceiveSecretReveal {
+            transaction_hash: Some(transaction_hash),
+            secret_registry_address,
+            secrethash,
+            secret,
+            block_number,
+            block_hash,
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use crate::enums::{Event, StateChange};
 use crate::errors::StateTransitionError;
+use crate::transfer::channel;
+use crate::transfer::event;
 use crate::transfer::state::TokenNetworkState;
 use crate::transfer::state_change;
+use web3::types::U256;
 
 pub struct TokenNetworkTransition {
     pub new_state: TokenNetworkState,
@@ -12,16 +15,126 @@ fn handle_contract_receive_channel_opened(
     mut token_network: TokenNetworkState,
     state_change: state_change::ContractReceiveChannelOpened,
 ) -> Result<TokenNetworkTransition, StateTransitionError> {
-    token_network.channelidentifiers_to_channels.insert(
-        state_change.channel_state.canonical_identifier.chain_identifier,
-        state_change.channel_state,
+    let canonical_identifier = state_change.channel_state.canonical_identifier.clone();
+    let channel_identifier = canonical_identifier.channel_identifier;
+    let our_address = state_change.channel_state.our_state.address;
+    let partner_address = state_change.channel_state.partner_state.address;
+
+    token_network
+        .network_graph
+        .add_channel(channel_identifier, our_address, partner_address);
+    token_network
+        .channelidentifiers_to_channels
+        .insert(channel_identifier, state_change.channel_state);
+    let channel_identifiers = token_network
+        .partneraddresses_to_channelidentifiers
+        .entry(partner_address)
+        .or_default();
+    if !channel_identifiers.contains(&channel_identifier) {
+        channel_identifiers.push(channel_identifier);
+    }
+
+    let channel_opened = event::ChannelOpened {
+        canonical_identifier,
+        block_number: state_change.block_number,
+    };
+
+    Ok(TokenNetworkTransition {
+        new_state: token_network,
+        events: vec![Event::ChannelOpened(channel_opened)],
+    })
+}
+
+fn handle_contract_receive_route_new(
+    mut token_network: TokenNetworkState,
+    state_change: state_change::ContractReceiveRouteNew,
+) -> Result<TokenNetworkTransition, StateTransitionError> {
+    token_network.network_graph.add_channel(
+        state_change.canonical_identifier.channel_identifier,
+        state_change.participant1,
+        state_change.participant2,
     );
+
     Ok(TokenNetworkTransition {
         new_state: token_network,
         events: vec![],
     })
 }
 
+/// Hands a state change of one of our channels to the channel state machine.
+/// Channels we do not participate in are not tracked and left alone.
+fn handle_channel_state_change(
+    mut token_network: TokenNetworkState,
+    channel_identifier: U256,
+    state_change: StateChange,
+) -> Result<TokenNetworkTransition, StateTransitionError> {
+    let channel_state = match token_network.channelidentifiers_to_channels.remove(&channel_identifier) {
+        Some(channel_state) => channel_state,
+        None => {
+            return Ok(TokenNetworkTransition {
+                new_state: token_network,
+                events: vec![],
+            })
+        }
+    };
+    let partner_address = channel_state.partner_state.address;
+
+    let transition = channel::state_transition(channel_state, state_change)?;
+    match transition.new_state {
+        Some(channel_state) => {
+            token_network
+                .channelidentifiers_to_channels
+                .insert(channel_identifier, channel_state);
+        }
+        None => {
+            let channel_identifiers = token_network
+                .partneraddresses_to_channelidentifiers
+                .get_mut(&partner_address);
+            if let Some(channel_identifiers) = channel_identifiers {
+                channel_identifiers.retain(|identifier| *identifier != channel_identifier);
+                if channel_identifiers.is_empty() {
+                    token_network
+                        .partneraddresses_to_channelidentifiers
+                        .remove(&partner_address);
+                }
+            }
+        }
+    }
+
+    Ok(TokenNetworkTransition {
+        new_state: token_network,
+        events: transition.events,
+    })
+}
+
+fn handle_contract_receive_channel_closed(
+    mut token_network: TokenNetworkState,
+    state_change: state_change::ContractReceiveChannelClosed,
+) -> Result<TokenNetworkTransition, StateTransitionError> {
+    let channel_identifier = state_change.canonical_identifier.channel_identifier;
+    token_network.network_graph.close_channel(channel_identifier);
+
+    handle_channel_state_change(
+        token_network,
+        channel_identifier,
+        StateChange::ContractReceiveChannelClosed(state_change),
+    )
+}
+
+fn handle_contract_receive_channel_settled(
+    mut token_network: TokenNetworkState,
+    state_change: state_change::ContractReceiveChannelSettled,
+) -> Result<TokenNetworkTransition, StateTransitionError> {
+    let channel_identifier = state_change.canonical_identifier.channel_identifier;
+    token_network.network_graph.remove_channel(channel_identifier);
+
+    handle_channel_state_change(
+        token_network,
+        channel_identifier,
+        StateChange::ContractReceiveChannelSettled(state_change),
+    )
+}
+
 pub fn state_transition(
     token_network: TokenNetworkState,
     state_change: StateChange,
@@ -30,6 +143,31 @@ pub fn state_transition(
         StateChange::ContractReceiveChannelOpened(state_change) => {
             handle_contract_receive_channel_opened(token_network, state_change)
         }
+        StateChange::ContractReceiveRouteNew(state_change) => {
+            handle_contract_receive_route_new(token_network, state_change)
+        }
+        StateChange::ContractReceiveChannelDeposit(state_change) => {
+            let channel_identifier = state_change.canonical_identifier.channel_identifier;
+            handle_channel_state_change(
+                token_network,
+                channel_identifier,
+                StateChange::ContractReceiveChannelDeposit(state_change),
+            )
+        }
+        StateChange::ContractReceiveChannelClosed(state_change) => {
+            handle_contract_receive_channel_closed(token_network, state_change)
+        }
+        StateChange::ContractReceiveUpdateTransfer(state_change) => {
+            let channel_identifier = state_change.canonical_identifier.channel_identifier;
+            handle_channel_state_change(
+                token_network,
+                channel_identifier,
+                StateChange::ContractReceiveUpdateTransfer(state_change),
+            )
+        }
+        StateChange::ContractReceiveChannelSettled(state_change) => {
+            handle_contract_receive_channel_settled(token_network, state_change)
+        }
         _ => Err(StateTransitionError {
             msg: String::from("Could not transition token network"),
         }),
@@ -1,19 +1,22 @@
-use crate::transfer::state::{ChainState, TokenNetworkRegistryState, TokenNetworkState};
-use web3::types::Address;
+use crate::transfer::state::{
+    CanonicalIdentifier, ChainState, ChannelState, TokenNetworkRegistryState, TokenNetworkState,
+};
+use web3::types::{Address, U256};
 
 pub fn get_token_network<'a>(
     chain_state: &'a ChainState,
-    token_network_address: &'a Address,
+    token_network_address: &Address,
 ) -> Option<&'a TokenNetworkState> {
-    let mut token_network: Option<&TokenNetworkState> = None;
-
     let token_network_registries = &chain_state.identifiers_to_tokennetworkregistries;
     for token_network_registry in token_network_registries.values() {
-        token_network = token_network_registry
+        let token_network = token_network_registry
             .tokennetworkaddresses_to_tokennetworks
             .get(token_network_address);
+        if token_network.is_some() {
+            return token_network;
+        }
     }
-    token_network.clone()
+    None
 }
 
 pub fn get_token_network_registry_by_token_network_address(
@@ -31,3 +34,29 @@ pub fn get_token_network_registry_by_token_network_address(
     }
     None
 }
+
+pub fn get_channel_by_canonical_identifier<'a>(
+    chain_state: &'a ChainState,
+    canonical_identifier: &CanonicalIdentifier,
+) -> Option<&'a ChannelState> {
+    let token_network = get_token_network(chain_state, &canonical_identifier.token_network_address)?;
+    token_network
+        .channelidentifiers_to_channels
+        .get(&canonical_identifier.channel_identifier)
+}
+
+/// Our on-chain deposit that has not been withdrawn yet.
+pub fn get_our_available_balance(channel_state: &ChannelState) -> U256 {
+    let our_state = &channel_state.our_state;
+    our_state
+        .contract_balance
+        .saturating_sub(our_state.onchain_total_withdraw)
+}
+
+/// The partner's on-chain deposit that has not been withdrawn yet.
+pub fn get_partner_available_balance(channel_state: &ChannelState) -> U256 {
+    let partner_state = &channel_state.partner_state;
+    partner_state
+        .contract_balance
+        .saturating_sub(partner_state.onchain_total_withdraw)
+}
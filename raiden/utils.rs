@@ -1 +1,21 @@
+use sha2::{Digest, Sha256};
+use web3::types::{H256, U256, U64};
 
+pub fn keccak256(data: &[u8]) -> H256 {
+    H256::from(tiny_keccak::keccak256(data))
+}
+
+/// Secrets are registered on-chain by their sha256 hash.
+pub fn sha256_secrethash(secret: &H256) -> H256 {
+    H256::from_slice(&Sha256::digest(secret.as_bytes()))
+}
+
+pub fn u256_to_bytes(value: U256) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    bytes
+}
+
+pub fn u64_to_bytes(value: U64) -> [u8; 32] {
+    u256_to_bytes(U256::from(value.as_u64()))
+}